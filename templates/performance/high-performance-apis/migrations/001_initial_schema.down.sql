@@ -0,0 +1,6 @@
+-- Revert initial database schema
+DROP TABLE IF EXISTS audit_logs;
+DROP TABLE IF EXISTS performance_metrics;
+DROP TABLE IF EXISTS rate_limits;
+DROP TABLE IF EXISTS api_keys;
+DROP TABLE IF EXISTS users;
@@ -0,0 +1,5 @@
+-- Revert performance optimizations and partitioning.
+-- Extensions are left installed since other database objects may use them.
+DROP FUNCTION IF EXISTS refresh_performance_summary();
+DROP MATERIALIZED VIEW IF EXISTS performance_summary;
+DROP TABLE IF EXISTS performance_metrics_partitioned CASCADE;
@@ -0,0 +1,65 @@
+-- description: Add performance optimizations and partitioning
+
+-- Enable performance-related extensions
+CREATE EXTENSION IF NOT EXISTS pg_stat_statements;
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Create partitioned table for high-volume performance metrics.
+-- Unique constraints on a partitioned table must include the partition key,
+-- so the primary key is (id, timestamp) rather than the copied one.
+CREATE TABLE IF NOT EXISTS performance_metrics_partitioned (
+    LIKE performance_metrics INCLUDING DEFAULTS INCLUDING CONSTRAINTS,
+    PRIMARY KEY (id, timestamp)
+) PARTITION BY RANGE (timestamp);
+
+CREATE INDEX IF NOT EXISTS idx_performance_metrics_partitioned_endpoint
+ON performance_metrics_partitioned(endpoint, timestamp);
+
+-- Create daily partitions for the last 7 days and next 7 days
+DO $$
+DECLARE
+    start_date DATE := CURRENT_DATE - INTERVAL '7 days';
+    end_date DATE := CURRENT_DATE + INTERVAL '7 days';
+    partition_date DATE := start_date;
+    partition_name TEXT;
+BEGIN
+    WHILE partition_date <= end_date LOOP
+        partition_name := 'performance_metrics_' || to_char(partition_date, 'YYYY_MM_DD');
+
+        EXECUTE format('
+            CREATE TABLE IF NOT EXISTS %I PARTITION OF performance_metrics_partitioned
+            FOR VALUES FROM (%L) TO (%L)',
+            partition_name,
+            partition_date,
+            partition_date + INTERVAL '1 day'
+        );
+
+        partition_date := partition_date + INTERVAL '1 day';
+    END LOOP;
+END $$;
+
+-- Create materialized view for performance analytics
+CREATE MATERIALIZED VIEW IF NOT EXISTS performance_summary AS
+SELECT
+    endpoint,
+    method,
+    DATE_TRUNC('hour', timestamp) as hour,
+    COUNT(*) as request_count,
+    AVG(response_time_ms) as avg_response_time,
+    PERCENTILE_CONT(0.95) WITHIN GROUP (ORDER BY response_time_ms) as p95_response_time,
+    PERCENTILE_CONT(0.99) WITHIN GROUP (ORDER BY response_time_ms) as p99_response_time,
+    COUNT(*) FILTER (WHERE status_code >= 400) as error_count
+FROM performance_metrics
+WHERE timestamp >= NOW() - INTERVAL '7 days'
+GROUP BY endpoint, method, hour;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_performance_summary_unique
+ON performance_summary(endpoint, method, hour);
+
+-- Create function to refresh performance summary
+CREATE OR REPLACE FUNCTION refresh_performance_summary()
+RETURNS void AS $$
+BEGIN
+    REFRESH MATERIALIZED VIEW CONCURRENTLY performance_summary;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,6 @@
+-- Revert rate limit quota plans
+DROP TRIGGER IF EXISTS api_keys_plan_changed ON api_keys;
+DROP TRIGGER IF EXISTS rate_limit_plans_changed ON rate_limit_plans;
+DROP FUNCTION IF EXISTS notify_rate_limit_plans_changed();
+ALTER TABLE api_keys DROP COLUMN IF EXISTS plan;
+DROP TABLE IF EXISTS rate_limit_plans;
@@ -0,0 +1,41 @@
+-- description: Add rate limit quota plans linked to API keys
+
+CREATE TABLE IF NOT EXISTS rate_limit_plans (
+    name VARCHAR(64) PRIMARY KEY,
+    requests_per_second INTEGER NOT NULL CHECK (requests_per_second > 0),
+    burst_size INTEGER NOT NULL CHECK (burst_size > 0),
+    algorithm VARCHAR(32) NOT NULL DEFAULT 'token_bucket'
+        CHECK (algorithm IN ('fixed_window', 'sliding_log', 'token_bucket')),
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+INSERT INTO rate_limit_plans (name, requests_per_second, burst_size) VALUES
+    ('free', 10, 20),
+    ('pro', 200, 400),
+    ('internal', 5000, 10000)
+ON CONFLICT (name) DO NOTHING;
+
+-- Keys without an explicit plan fall back to a `plan:<name>` permission,
+-- then to the configured default
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS plan VARCHAR(64)
+    REFERENCES rate_limit_plans(name) ON UPDATE CASCADE;
+
+-- Let running servers drop cached plans as soon as they change
+CREATE OR REPLACE FUNCTION notify_rate_limit_plans_changed()
+RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('rate_limit_plans_changed', TG_TABLE_NAME);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS rate_limit_plans_changed ON rate_limit_plans;
+CREATE TRIGGER rate_limit_plans_changed
+AFTER INSERT OR UPDATE OR DELETE ON rate_limit_plans
+FOR EACH STATEMENT EXECUTE FUNCTION notify_rate_limit_plans_changed();
+
+DROP TRIGGER IF EXISTS api_keys_plan_changed ON api_keys;
+CREATE TRIGGER api_keys_plan_changed
+AFTER UPDATE OF plan, permissions, expires_at OR DELETE ON api_keys
+FOR EACH STATEMENT EXECUTE FUNCTION notify_rate_limit_plans_changed();
@@ -0,0 +1,12 @@
+-- Revert API key lookup prefixes, rotation and revocation
+DROP TRIGGER IF EXISTS api_keys_plan_changed ON api_keys;
+CREATE TRIGGER api_keys_plan_changed
+AFTER UPDATE OF plan, permissions, expires_at OR DELETE ON api_keys
+FOR EACH STATEMENT EXECUTE FUNCTION notify_rate_limit_plans_changed();
+
+DROP TRIGGER IF EXISTS api_keys_changed ON api_keys;
+DROP FUNCTION IF EXISTS notify_api_keys_changed();
+DROP INDEX IF EXISTS idx_api_keys_key_prefix;
+ALTER TABLE api_keys DROP COLUMN IF EXISTS rotated_from;
+ALTER TABLE api_keys DROP COLUMN IF EXISTS revoked_at;
+ALTER TABLE api_keys DROP COLUMN IF EXISTS key_prefix;
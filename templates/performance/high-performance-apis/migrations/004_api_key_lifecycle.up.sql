@@ -0,0 +1,30 @@
+-- description: Track API key lookup prefixes, rotation and revocation
+
+-- Keys are `hpk_<prefix>_<secret>`; the prefix finds the row, whose key_hash
+-- (argon2) then verifies the whole key
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS key_prefix VARCHAR(32);
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS revoked_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS rotated_from UUID
+    REFERENCES api_keys(id) ON DELETE SET NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_api_keys_key_prefix ON api_keys(key_prefix);
+
+-- Let running servers drop cached key verifications as soon as a key changes
+CREATE OR REPLACE FUNCTION notify_api_keys_changed()
+RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('api_keys_changed', TG_OP);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS api_keys_changed ON api_keys;
+CREATE TRIGGER api_keys_changed
+AFTER UPDATE OF permissions, expires_at, revoked_at OR DELETE ON api_keys
+FOR EACH STATEMENT EXECUTE FUNCTION notify_api_keys_changed();
+
+-- A revoked key no longer has a plan
+DROP TRIGGER IF EXISTS api_keys_plan_changed ON api_keys;
+CREATE TRIGGER api_keys_plan_changed
+AFTER UPDATE OF plan, permissions, expires_at, revoked_at OR DELETE ON api_keys
+FOR EACH STATEMENT EXECUTE FUNCTION notify_rate_limit_plans_changed();
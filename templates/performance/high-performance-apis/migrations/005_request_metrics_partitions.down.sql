@@ -0,0 +1,19 @@
+-- Summarize request metrics from performance_metrics again
+DROP MATERIALIZED VIEW IF EXISTS performance_summary;
+
+CREATE MATERIALIZED VIEW performance_summary AS
+SELECT
+    endpoint,
+    method,
+    DATE_TRUNC('hour', timestamp) as hour,
+    COUNT(*) as request_count,
+    AVG(response_time_ms) as avg_response_time,
+    PERCENTILE_CONT(0.95) WITHIN GROUP (ORDER BY response_time_ms) as p95_response_time,
+    PERCENTILE_CONT(0.99) WITHIN GROUP (ORDER BY response_time_ms) as p99_response_time,
+    COUNT(*) FILTER (WHERE status_code >= 400) as error_count
+FROM performance_metrics
+WHERE timestamp >= NOW() - INTERVAL '7 days'
+GROUP BY endpoint, method, hour;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_performance_summary_unique
+ON performance_summary(endpoint, method, hour);
@@ -0,0 +1,2 @@
+-- Drop the maintenance job run history
+DROP TABLE IF EXISTS job_runs;
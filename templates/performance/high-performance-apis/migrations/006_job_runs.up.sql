@@ -0,0 +1,20 @@
+-- description: Record maintenance job runs
+
+CREATE TABLE IF NOT EXISTS job_runs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    job VARCHAR(100) NOT NULL,
+    -- 'schedule' or 'manual'
+    triggered_by VARCHAR(20) NOT NULL,
+    -- The schedule slot this run covers; NULL for manual runs
+    scheduled_for TIMESTAMP WITH TIME ZONE,
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMP WITH TIME ZONE,
+    -- 'running', 'succeeded', 'failed' or 'abandoned'
+    status VARCHAR(20) NOT NULL DEFAULT 'running',
+    output TEXT,
+    instance VARCHAR(255)
+);
+
+-- Each scheduled slot runs once across all replicas
+CREATE UNIQUE INDEX IF NOT EXISTS idx_job_runs_slot ON job_runs(job, scheduled_for);
+CREATE INDEX IF NOT EXISTS idx_job_runs_job_started ON job_runs(job, started_at DESC);
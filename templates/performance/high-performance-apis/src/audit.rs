@@ -0,0 +1,451 @@
+use anyhow::Context;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, OriginalUri, Query, State},
+    http::{header::USER_AGENT, request::Parts, Extensions, HeaderMap, Request},
+    response::Json,
+    routing::get,
+    Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{convert::Infallible, net::IpAddr, sync::Arc};
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{
+    auth::Principal,
+    batch::{BatchSink, BatchWriter},
+    client_ip::ClientIp,
+    config::{reload::changed_sections, AuditConfig, Config},
+    database::DatabasePool,
+    error::AppError,
+    metrics::Metrics,
+    middleware::scope::RequireScopeLayer,
+    AppState,
+};
+
+/// Actions recorded in `audit_logs.action`
+pub mod actions {
+    /// A request to an `/admin` route
+    pub const ADMIN_REQUEST: &str = "admin.request";
+    /// A request rejected for missing or invalid credentials
+    pub const AUTH_FAILURE: &str = "auth.failure";
+    pub const API_KEY_CREATED: &str = "api_key.created";
+    pub const API_KEY_ROTATED: &str = "api_key.rotated";
+    pub const API_KEY_REVOKED: &str = "api_key.revoked";
+    pub const CONFIG_RELOADED: &str = "config.reloaded";
+}
+
+/// Longest user agent stored with an event
+const MAX_USER_AGENT_LEN: usize = 512;
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+/// One entry for `audit_logs`
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    pub user_id: Option<Uuid>,
+    pub action: &'static str,
+    pub resource_type: Option<&'static str>,
+    pub resource_id: Option<Uuid>,
+    pub details: serde_json::Map<String, serde_json::Value>,
+    pub ip_address: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl AuditEvent {
+    pub fn new(action: &'static str) -> Self {
+        Self {
+            user_id: None,
+            action,
+            resource_type: None,
+            resource_id: None,
+            details: serde_json::Map::new(),
+            ip_address: None,
+            user_agent: None,
+            timestamp: Utc::now(),
+        }
+    }
+
+    /// Attribute the event to the caller. The principal is also kept in
+    /// `details`, since token subjects need not be rows in `users`.
+    pub fn actor(mut self, principal: &Principal) -> Self {
+        self.user_id = principal.user_id();
+        let actor = match principal {
+            Principal::Token(claims) => serde_json::json!({ "type": "token", "sub": claims.sub }),
+            Principal::ApiKey(identity) => serde_json::json!({ "type": "api_key", "id": identity.id }),
+            Principal::Certificate(certificate) => serde_json::json!({
+                "type": "certificate",
+                "subject": certificate.subject,
+                "fingerprint": certificate.fingerprint,
+            }),
+        };
+        self.details.insert("actor".to_string(), actor);
+        self
+    }
+
+    pub fn resource(mut self, resource_type: &'static str, id: Uuid) -> Self {
+        self.resource_type = Some(resource_type);
+        self.resource_id = Some(id);
+        self
+    }
+
+    pub fn detail(mut self, key: &str, value: impl Into<serde_json::Value>) -> Self {
+        self.details.insert(key.to_string(), value.into());
+        self
+    }
+
+    /// Record the request's method and path. Nested routers see the path
+    /// without their prefix, so it is taken from the original URI.
+    pub fn request<B>(self, request: &Request<B>) -> Self {
+        let path = match request.extensions().get::<OriginalUri>() {
+            Some(OriginalUri(uri)) => uri.path(),
+            None => request.uri().path(),
+        };
+        let path = path.to_string();
+        self.detail("method", request.method().as_str()).detail("path", path)
+    }
+
+    pub fn source(mut self, source: &AuditSource) -> Self {
+        self.ip_address = source.ip_address;
+        self.user_agent = source.user_agent.clone();
+        self
+    }
+}
+
+/// Where a request came from, as recorded with its audit events
+#[derive(Debug, Clone, Default)]
+pub struct AuditSource {
+    pub ip_address: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
+impl AuditSource {
+    pub fn from_parts(extensions: &Extensions, headers: &HeaderMap) -> Self {
+        Self {
+            ip_address: extensions.get::<ClientIp>().map(|ClientIp(ip)| *ip),
+            user_agent: headers
+                .get(USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|agent| agent.chars().take(MAX_USER_AGENT_LEN).collect()),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuditSource
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_parts(&parts.extensions, &parts.headers))
+    }
+}
+
+/// Records audit events without blocking the caller. Events go through a
+/// bounded channel to an [`AuditWriter`]; when the channel is full they are
+/// dropped and counted in `audit_events_dropped_total`.
+#[derive(Clone)]
+pub struct AuditLog {
+    events: Option<mpsc::Sender<AuditEvent>>,
+    metrics: Metrics,
+}
+
+impl AuditLog {
+    /// The log and the writer that drains it, which the caller must spawn.
+    /// The writer is `None` when auditing is disabled.
+    pub fn new(db: DatabasePool, config: &AuditConfig, metrics: Metrics) -> (Self, Option<AuditWriter>) {
+        if !config.enabled {
+            return (Self::disabled(), None);
+        }
+
+        let (events, receiver) = mpsc::channel(config.channel_capacity);
+        let sink = AuditSink {
+            db,
+            metrics: metrics.clone(),
+        };
+        let writer = BatchWriter::new(sink, receiver, config.batch_size, config.flush_interval);
+        let log = Self {
+            events: Some(events),
+            metrics,
+        };
+        (log, Some(writer))
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            events: None,
+            metrics: Metrics::disabled(),
+        }
+    }
+
+    pub fn record(&self, event: AuditEvent) {
+        let Some(events) = &self.events else {
+            return;
+        };
+
+        match events.try_send(event) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(event)) => {
+                self.metrics.record_audit_events(0, 1);
+                debug!("Audit log queue full, dropped {} event", event.action);
+            }
+            Err(mpsc::error::TrySendError::Closed(event)) => {
+                self.metrics.record_audit_events(0, 1);
+                warn!("Audit log writer stopped, dropped {} event", event.action);
+            }
+        }
+    }
+
+    /// Record every applied configuration reload with the sections it changed
+    pub async fn watch_config(self, mut updates: watch::Receiver<Arc<Config>>) {
+        let mut current = updates.borrow_and_update().clone();
+
+        while updates.changed().await.is_ok() {
+            let next = updates.borrow_and_update().clone();
+            let sections = changed_sections(&current, &next).unwrap_or_default();
+            self.record(AuditEvent::new(actions::CONFIG_RELOADED).detail("sections", sections));
+            current = next;
+        }
+    }
+}
+
+/// Drains queued audit events into `audit_logs`
+pub type AuditWriter = BatchWriter<AuditSink>;
+
+/// Writes audit events to `audit_logs`, one insert per batch
+pub struct AuditSink {
+    db: DatabasePool,
+    metrics: Metrics,
+}
+
+#[async_trait]
+impl BatchSink for AuditSink {
+    type Item = AuditEvent;
+
+    fn record(&self, written: u64, dropped: u64) {
+        self.metrics.record_audit_events(written, dropped);
+    }
+
+    async fn write(&self, batch: &[AuditEvent]) -> anyhow::Result<()> {
+        let mut user_ids = Vec::with_capacity(batch.len());
+        let mut actions = Vec::with_capacity(batch.len());
+        let mut resource_types = Vec::with_capacity(batch.len());
+        let mut resource_ids = Vec::with_capacity(batch.len());
+        let mut details = Vec::with_capacity(batch.len());
+        let mut ip_addresses = Vec::with_capacity(batch.len());
+        let mut user_agents = Vec::with_capacity(batch.len());
+        let mut timestamps = Vec::with_capacity(batch.len());
+        for event in batch {
+            user_ids.push(event.user_id);
+            actions.push(event.action);
+            resource_types.push(event.resource_type);
+            resource_ids.push(event.resource_id);
+            details.push(serde_json::Value::Object(event.details.clone()));
+            ip_addresses.push(event.ip_address.map(|ip| ip.to_string()));
+            user_agents.push(event.user_agent.as_deref());
+            timestamps.push(event.timestamp);
+        }
+
+        // The join drops user ids with no `users` row, which would otherwise
+        // fail the foreign key and with it the whole batch
+        sqlx::query(
+            "INSERT INTO audit_logs
+                 (user_id, action, resource_type, resource_id, details, ip_address, user_agent, timestamp)
+             SELECT users.id, e.action, e.resource_type, e.resource_id, e.details, e.ip_address::inet,
+                    e.user_agent, e.timestamp
+             FROM UNNEST($1::uuid[], $2::text[], $3::text[], $4::uuid[], $5::jsonb[], $6::text[],
+                         $7::text[], $8::timestamptz[])
+                 AS e(user_id, action, resource_type, resource_id, details, ip_address, user_agent, timestamp)
+             LEFT JOIN users ON users.id = e.user_id",
+        )
+        .bind(&user_ids)
+        .bind(&actions)
+        .bind(&resource_types)
+        .bind(&resource_ids)
+        .bind(&details)
+        .bind(&ip_addresses)
+        .bind(&user_agents)
+        .bind(&timestamps)
+        .execute(&self.db)
+        .await
+        .with_context(|| format!("failed to write {} audit events", batch.len()))?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    pub user_id: Option<Uuid>,
+    pub action: String,
+    pub resource_type: Option<String>,
+    pub resource_id: Option<Uuid>,
+    pub details: Option<serde_json::Value>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditFilter {
+    pub user_id: Option<Uuid>,
+    pub action: Option<String>,
+    /// Only events at or after this time
+    pub from: Option<DateTime<Utc>>,
+    /// Only events before this time
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    /// Pass as `cursor` for the next page; absent on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// Position after the last entry of a page, newest first
+fn encode_cursor(timestamp: DateTime<Utc>, id: Uuid) -> String {
+    BASE64_URL.encode(format!("{}|{}", timestamp.timestamp_micros(), id))
+}
+
+fn decode_cursor(cursor: &str) -> Option<(DateTime<Utc>, Uuid)> {
+    let decoded = String::from_utf8(BASE64_URL.decode(cursor).ok()?).ok()?;
+    let (micros, id) = decoded.split_once('|')?;
+    Some((DateTime::from_timestamp_micros(micros.parse().ok()?)?, id.parse().ok()?))
+}
+
+/// Events matching `filter`, newest first. Pages are keyed on
+/// `(timestamp, id)`, so deep pages cost the same as the first.
+pub async fn query(db: &DatabasePool, filter: &AuditFilter) -> Result<AuditPage, AppError> {
+    let limit = filter.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::BadRequest(format!("limit must be between 1 and {}", MAX_PAGE_SIZE)));
+    }
+    let after = filter
+        .cursor
+        .as_deref()
+        .map(|cursor| decode_cursor(cursor).ok_or_else(|| AppError::BadRequest("invalid cursor".to_string())))
+        .transpose()?;
+
+    let mut entries = sqlx::query_as::<_, AuditEntry>(
+        "SELECT id, user_id, action, resource_type, resource_id, details, host(ip_address) AS ip_address,
+                user_agent, timestamp
+         FROM audit_logs
+         WHERE ($1::uuid IS NULL OR user_id = $1)
+           AND ($2::text IS NULL OR action = $2)
+           AND ($3::timestamptz IS NULL OR timestamp >= $3)
+           AND ($4::timestamptz IS NULL OR timestamp < $4)
+           AND ($5::timestamptz IS NULL OR (timestamp, id) < ($5, $6))
+         ORDER BY timestamp DESC, id DESC
+         LIMIT $7",
+    )
+    .bind(filter.user_id)
+    .bind(filter.action.as_deref())
+    .bind(filter.from)
+    .bind(filter.to)
+    .bind(after.map(|(timestamp, _)| timestamp))
+    .bind(after.map(|(_, id)| id))
+    .bind(limit + 1)
+    .fetch_all(db)
+    .await?;
+
+    let next_cursor = if entries.len() as i64 > limit {
+        entries.truncate(limit as usize);
+        entries.last().map(|last| encode_cursor(last.timestamp, last.id))
+    } else {
+        None
+    };
+
+    Ok(AuditPage { entries, next_cursor })
+}
+
+/// `/admin/audit`, readable with `audit:read`
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/", get(list_events).layer(RequireScopeLayer::new(["audit:read"])))
+}
+
+async fn list_events(
+    State(state): State<AppState>,
+    Query(filter): Query<AuditFilter>,
+) -> Result<Json<AuditPage>, AppError> {
+    Ok(Json(query(&state.db, &filter).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Claims;
+
+    #[test]
+    fn test_cursor_round_trips() {
+        let timestamp = DateTime::from_timestamp_micros(1_718_000_000_123_456).unwrap();
+        let id = Uuid::new_v4();
+
+        assert_eq!(decode_cursor(&encode_cursor(timestamp, id)), Some((timestamp, id)));
+        assert_eq!(decode_cursor("not-a-cursor"), None);
+        assert_eq!(decode_cursor(&BASE64_URL.encode("123|not-a-uuid")), None);
+    }
+
+    #[test]
+    fn test_event_records_actor_and_source() {
+        let user_id = Uuid::new_v4();
+        let principal = Principal::Token(Claims {
+            sub: user_id.to_string(),
+            exp: 0,
+            iat: 0,
+            iss: None,
+            aud: None,
+            scope: None,
+            permissions: vec![],
+        });
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, "x".repeat(1000).parse().unwrap());
+        let mut extensions = Extensions::new();
+        extensions.insert(ClientIp("203.0.113.7".parse().unwrap()));
+
+        let event = AuditEvent::new(actions::ADMIN_REQUEST)
+            .actor(&principal)
+            .source(&AuditSource::from_parts(&extensions, &headers));
+        assert_eq!(event.user_id, Some(user_id));
+        assert_eq!(event.details["actor"]["type"], "token");
+        assert_eq!(event.ip_address, Some("203.0.113.7".parse().unwrap()));
+        assert_eq!(event.user_agent.map(|agent| agent.len()), Some(MAX_USER_AGENT_LEN));
+    }
+
+    #[tokio::test]
+    async fn test_nested_requests_record_the_full_path() {
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let (events, mut receiver) = mpsc::channel(8);
+        let audit = AuditLog {
+            events: Some(events),
+            metrics: Metrics::disabled(),
+        };
+        let admin = Router::new()
+            .route("/api-keys", get(|| async { "ok" }))
+            .layer(crate::middleware::audit::AuditLayer::new(audit));
+        let app = Router::new().nest("/admin", admin);
+
+        let request = Request::get("/admin/api-keys").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+
+        let event = receiver.recv().await.unwrap();
+        assert_eq!(event.action, actions::ADMIN_REQUEST);
+        assert_eq!(event.details["method"], "GET");
+        assert_eq!(event.details["path"], "/admin/api-keys");
+    }
+}
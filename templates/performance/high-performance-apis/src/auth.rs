@@ -0,0 +1,418 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, State},
+    http::{
+        header::{AUTHORIZATION, CACHE_CONTROL, WWW_AUTHENTICATE},
+        request::Parts,
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Json, Response},
+};
+use chrono::Utc;
+use jsonwebtoken::{
+    errors::ErrorKind,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::IpAddr,
+    sync::{Arc, RwLock},
+};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::{
+    client_ip::ClientIp,
+    config::{Config, SecurityConfig},
+    problem::ProblemDetails,
+    server::tls::ClientCertificate,
+    AppState,
+};
+
+pub mod api_keys;
+pub mod jwks;
+pub mod keys;
+pub mod scopes;
+
+use self::{
+    api_keys::{api_key_from_headers, ApiKeyIdentity, ApiKeyStore},
+    jwks::RemoteJwks,
+    keys::KeyRing,
+};
+
+/// Claims of a verified JWT
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: u64,
+    #[serde(default)]
+    pub iat: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// A single audience or a list, as RFC 7519 allows
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<serde_json::Value>,
+    /// Space-separated OAuth scopes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// Scopes as a list, as issued by some identity providers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permissions: Vec<String>,
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum AuthError {
+    #[error("missing bearer token or API key")]
+    MissingToken,
+    #[error("token has expired")]
+    Expired,
+    #[error("token was signed with unknown key '{0}'")]
+    UnknownKey(String),
+    #[error("invalid token: {0}")]
+    InvalidToken(String),
+    #[error("authentication is temporarily unavailable")]
+    Unavailable,
+    #[error("too many failed API key attempts, try again later")]
+    TooManyAttempts,
+}
+
+impl From<jsonwebtoken::errors::Error> for AuthError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        match e.kind() {
+            ErrorKind::ExpiredSignature => Self::Expired,
+            _ => Self::InvalidToken(e.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        if let Self::Unavailable = self {
+            return ProblemDetails::new(StatusCode::SERVICE_UNAVAILABLE)
+                .with_detail(self.to_string())
+                .into_response();
+        }
+        if let Self::TooManyAttempts = self {
+            return ProblemDetails::new(StatusCode::TOO_MANY_REQUESTS)
+                .with_detail(self.to_string())
+                .into_response();
+        }
+
+        let challenge = match self {
+            Self::MissingToken => "Bearer",
+            _ => r#"Bearer error="invalid_token""#,
+        };
+
+        let mut response = ProblemDetails::new(StatusCode::UNAUTHORIZED)
+            .with_detail(self.to_string())
+            .into_response();
+        response
+            .headers_mut()
+            .insert(WWW_AUTHENTICATE, HeaderValue::from_static(challenge));
+        response
+    }
+}
+
+/// Verifies bearer tokens against the local key ring and, when
+/// `security.jwks_url` is set, an external issuer's published keys
+#[derive(Clone)]
+pub struct JwtVerifier {
+    keys: Arc<RwLock<Arc<KeyRing>>>,
+    remote: Option<Arc<RemoteJwks>>,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+impl JwtVerifier {
+    pub fn from_config(config: &SecurityConfig) -> anyhow::Result<Self> {
+        let remote = config
+            .jwks_url
+            .as_deref()
+            .map(RemoteJwks::new)
+            .transpose()?
+            .map(Arc::new);
+
+        Ok(Self {
+            keys: Arc::new(RwLock::new(Arc::new(KeyRing::from_config(config)?))),
+            remote,
+            issuer: config.jwt_issuer.clone(),
+            audience: config.jwt_audience.clone(),
+        })
+    }
+
+    pub fn keys(&self) -> Arc<KeyRing> {
+        self.keys.read().unwrap().clone()
+    }
+
+    /// The external JWKS, which should be kept fresh with [`RemoteJwks::run`]
+    pub fn remote_jwks(&self) -> Option<Arc<RemoteJwks>> {
+        self.remote.clone()
+    }
+
+    fn validation(&self, algorithm: Algorithm) -> Validation {
+        let mut validation = Validation::new(algorithm);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        validation
+    }
+
+    fn decode(&self, token: &str, key: &DecodingKey, algorithm: Algorithm) -> jsonwebtoken::errors::Result<Claims> {
+        jsonwebtoken::decode(token, key, &self.validation(algorithm)).map(|data| data.claims)
+    }
+
+    pub async fn verify(&self, token: &str) -> Result<Claims, AuthError> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let now = Utc::now();
+        let keys = self.keys();
+
+        let Some(kid) = header.kid else {
+            // Tokens issued before key ids were introduced: try each key
+            // that could have signed them
+            for key in keys.candidates(header.alg, now) {
+                match self.decode(token, &key.key, key.algorithm) {
+                    Err(e) if *e.kind() == ErrorKind::InvalidSignature => continue,
+                    result => return Ok(result?),
+                }
+            }
+            return Err(AuthError::InvalidToken("no key matches the signature".to_string()));
+        };
+
+        if let Some(key) = keys.find(&kid, now) {
+            if key.algorithm != header.alg {
+                return Err(AuthError::InvalidToken(format!("key '{}' does not use {:?}", kid, header.alg)));
+            }
+            return Ok(self.decode(token, &key.key, key.algorithm)?);
+        }
+
+        let Some(remote) = &self.remote else {
+            return Err(AuthError::UnknownKey(kid));
+        };
+        let jwk = remote
+            .find_or_refresh(&kid)
+            .await
+            .ok_or_else(|| AuthError::UnknownKey(kid.clone()))?;
+        self.decode_remote(token, &jwk, header.alg)
+    }
+
+    fn decode_remote(&self, token: &str, jwk: &Jwk, algorithm: Algorithm) -> Result<Claims, AuthError> {
+        // Published keys are public, so a shared-secret algorithm would let
+        // anyone sign with them
+        if matches!(algorithm, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+            return Err(AuthError::InvalidToken(format!("{:?} is not accepted for published keys", algorithm)));
+        }
+        if let Some(key_algorithm) = &jwk.common.key_algorithm {
+            if serde_json::to_value(key_algorithm).ok() != serde_json::to_value(algorithm).ok() {
+                return Err(AuthError::InvalidToken(format!("key does not use {:?}", algorithm)));
+            }
+        }
+
+        let key = DecodingKey::from_jwk(jwk)?;
+        Ok(self.decode(token, &key, algorithm)?)
+    }
+
+    /// Rebuild the key ring when a config reload changes the JWT keys. A
+    /// ring that fails to build is already rejected by `Config::validate`.
+    pub async fn watch_config(self, mut updates: watch::Receiver<Arc<Config>>) {
+        let mut current = updates.borrow().security.clone();
+
+        while updates.changed().await.is_ok() {
+            let security = updates.borrow_and_update().security.clone();
+            if security.jwt_keys == current.jwt_keys && security.jwt_signing_kid == current.jwt_signing_kid {
+                continue;
+            }
+
+            match KeyRing::from_config(&security) {
+                Ok(keys) => {
+                    *self.keys.write().unwrap() = Arc::new(keys);
+                    info!("JWT keys reloaded");
+                    current = security;
+                }
+                Err(e) => warn!("Failed to reload JWT keys: {:#}", e),
+            }
+        }
+    }
+}
+
+/// The authenticated caller, added to request extensions by `AuthLayer`
+#[derive(Debug, Clone)]
+pub enum Principal {
+    Token(Claims),
+    ApiKey(ApiKeyIdentity),
+    /// An mTLS client certificate, when the request carries no other credentials
+    Certificate(ClientCertificate),
+}
+
+impl Principal {
+    /// The user behind the request; tokens name it in `sub`
+    pub fn user_id(&self) -> Option<uuid::Uuid> {
+        match self {
+            Self::Token(claims) => claims.sub.parse().ok(),
+            Self::ApiKey(identity) => Some(identity.user_id),
+            Self::Certificate(_) => None,
+        }
+    }
+}
+
+/// Authenticates requests by JWT or API key
+#[derive(Clone)]
+pub struct Authenticator {
+    jwt: JwtVerifier,
+    api_keys: ApiKeyStore,
+}
+
+impl Authenticator {
+    pub fn new(jwt: JwtVerifier, api_keys: ApiKeyStore) -> Self {
+        Self { jwt, api_keys }
+    }
+
+    pub fn jwt(&self) -> &JwtVerifier {
+        &self.jwt
+    }
+
+    pub fn api_keys(&self) -> &ApiKeyStore {
+        &self.api_keys
+    }
+
+    /// Authenticate with `X-API-Key` or a bearer token, which is an API key
+    /// when it has the `hpk_` prefix and a JWT otherwise. Without either, a
+    /// verified client certificate authenticates the connection's caller.
+    /// Failed API keys are counted against `client_ip`.
+    pub async fn authenticate(
+        &self,
+        headers: &HeaderMap,
+        certificate: Option<&ClientCertificate>,
+        client_ip: Option<IpAddr>,
+    ) -> Result<Principal, AuthError> {
+        if let Some(key) = api_key_from_headers(headers) {
+            return self.api_keys.authenticate(key, client_ip).await.map(Principal::ApiKey);
+        }
+
+        let token = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+        match (token, certificate) {
+            (Some(token), _) => self.jwt.verify(token).await.map(Principal::Token),
+            (None, Some(certificate)) => Ok(Principal::Certificate(certificate.clone())),
+            (None, None) => Err(AuthError::MissingToken),
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Principal {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        if let Some(principal) = parts.extensions.get::<Principal>() {
+            return Ok(principal.clone());
+        }
+
+        let certificate = parts.extensions.get::<ClientCertificate>();
+        let client_ip = parts.extensions.get::<ClientIp>().map(|ClientIp(ip)| *ip);
+        let principal = state.auth.authenticate(&parts.headers, certificate, client_ip).await?;
+        parts.extensions.insert(principal.clone());
+        Ok(principal)
+    }
+}
+
+/// `GET /.well-known/jwks.json`: public keys that verify this service's
+/// tokens, including retired keys still inside their rotation window
+pub async fn jwks(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(CACHE_CONTROL, "public, max-age=300")],
+        Json(state.auth.jwt().keys().jwks(Utc::now())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::JwtAlgorithm;
+    use keys::tests::{key, EC_PRIVATE_KEY, EC_PUBLIC_KEY};
+
+    fn claims(exp: i64) -> serde_json::Value {
+        serde_json::json!({ "sub": "admin", "exp": exp, "scope": "admin:read" })
+    }
+
+    fn hs256(kid: &str, secret: &str) -> crate::config::JwtKeyConfig {
+        crate::config::JwtKeyConfig {
+            kid: kid.to_string(),
+            algorithm: JwtAlgorithm::HS256,
+            secret: Some(secret.repeat(32)),
+            private_key: None,
+            public_key: None,
+            verify_until: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retired_key_verifies_until_window_closes() {
+        let mut config = Config::default().security;
+        config.jwt_keys = vec![hs256("2024-01", "a")];
+        let old_token = KeyRing::from_config(&config).unwrap().sign(&claims(u32::MAX as i64)).unwrap();
+
+        // Rotate: the new key signs, the old one still verifies
+        config.jwt_keys.insert(0, hs256("2024-02", "b"));
+        config.jwt_keys[1].verify_until = Some(Utc::now() + chrono::Duration::hours(1));
+        let verifier = JwtVerifier::from_config(&config).unwrap();
+        assert_eq!(verifier.verify(&old_token).await.unwrap().sub, "admin");
+        let new_token = verifier.keys().sign(&claims(u32::MAX as i64)).unwrap();
+        assert_eq!(jsonwebtoken::decode_header(&new_token).unwrap().kid.as_deref(), Some("2024-02"));
+
+        config.jwt_keys[1].verify_until = Some(Utc::now() - chrono::Duration::hours(1));
+        let verifier = JwtVerifier::from_config(&config).unwrap();
+        assert!(matches!(verifier.verify(&old_token).await, Err(AuthError::UnknownKey(kid)) if kid == "2024-01"));
+    }
+
+    #[tokio::test]
+    async fn test_tokens_without_kid_use_jwt_secret() {
+        let config = Config::default().security;
+        let legacy = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &claims(u32::MAX as i64),
+            &jsonwebtoken::EncodingKey::from_secret(config.jwt_secret.as_bytes()),
+        )
+        .unwrap();
+        let expired = KeyRing::from_config(&config).unwrap().sign(&claims(1)).unwrap();
+
+        let verifier = JwtVerifier::from_config(&config).unwrap();
+        assert_eq!(verifier.verify(&legacy).await.unwrap().scope.as_deref(), Some("admin:read"));
+        assert!(matches!(verifier.verify(&expired).await, Err(AuthError::Expired)));
+    }
+
+    #[tokio::test]
+    async fn test_external_jwks_file() {
+        // An external issuer that publishes its ES256 key
+        let mut issuer = Config::default().security;
+        issuer.jwt_keys = vec![key("issuer-1", JwtAlgorithm::ES256, Some(EC_PRIVATE_KEY), EC_PUBLIC_KEY)];
+        let issuer = KeyRing::from_config(&issuer).unwrap();
+        let path = std::env::temp_dir().join(format!("jwks-test-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, serde_json::to_string(&issuer.jwks(Utc::now())).unwrap()).unwrap();
+
+        let mut config = Config::default().security;
+        config.jwks_url = Some(format!("file://{}", path.display()));
+        let verifier = JwtVerifier::from_config(&config).unwrap();
+
+        // The first token with the issuer's kid fetches the set
+        let token = issuer.sign(&claims(u32::MAX as i64)).unwrap();
+        assert_eq!(verifier.verify(&token).await.unwrap().sub, "admin");
+
+        let mut forged = jsonwebtoken::Header::new(Algorithm::ES256);
+        forged.kid = Some("issuer-2".to_string());
+        let forged = jsonwebtoken::encode(
+            &forged,
+            &claims(u32::MAX as i64),
+            &jsonwebtoken::EncodingKey::from_ec_pem(EC_PRIVATE_KEY.as_bytes()).unwrap(),
+        )
+        .unwrap();
+        assert!(matches!(verifier.verify(&forged).await, Err(AuthError::UnknownKey(_))));
+    }
+}
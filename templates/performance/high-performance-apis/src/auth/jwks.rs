@@ -0,0 +1,107 @@
+use anyhow::Context;
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+/// Unknown `kid`s trigger at most one refetch per interval, so tokens with
+/// made-up key ids can't be used to hammer the issuer
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Keys published by an external issuer, fetched from its JWKS URL. A
+/// `file://` URL reads a local JWKS file instead, which stands in for the
+/// issuer in tests and air-gapped deployments.
+pub struct RemoteJwks {
+    url: String,
+    client: reqwest::Client,
+    keys: RwLock<Arc<JwkSet>>,
+    // Held while fetching so concurrent refetches coalesce
+    last_fetch: Mutex<Option<Instant>>,
+}
+
+impl RemoteJwks {
+    pub fn new(url: impl Into<String>) -> anyhow::Result<Self> {
+        Ok(Self {
+            url: url.into(),
+            client: reqwest::Client::builder().timeout(Duration::from_secs(10)).build()?,
+            keys: RwLock::new(Arc::new(JwkSet { keys: vec![] })),
+            last_fetch: Mutex::new(None),
+        })
+    }
+
+    async fn fetch(&self) -> anyhow::Result<JwkSet> {
+        if let Some(path) = self.url.strip_prefix("file://") {
+            let contents = tokio::fs::read_to_string(path)
+                .await
+                .with_context(|| format!("failed to read {}", path))?;
+            return serde_json::from_str(&contents).with_context(|| format!("{} is not a JWKS", path));
+        }
+
+        Ok(self
+            .client
+            .get(&self.url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    /// Replace the cached keys with the issuer's current set, returning how
+    /// many it published
+    pub async fn refresh(&self) -> anyhow::Result<usize> {
+        let mut last_fetch = self.last_fetch.lock().await;
+        self.refresh_locked(&mut last_fetch).await
+    }
+
+    async fn refresh_locked(&self, last_fetch: &mut Option<Instant>) -> anyhow::Result<usize> {
+        *last_fetch = Some(Instant::now());
+        let keys = self
+            .fetch()
+            .await
+            .with_context(|| format!("failed to fetch JWKS from {}", self.url))?;
+        let count = keys.keys.len();
+        *self.keys.write().unwrap() = Arc::new(keys);
+        Ok(count)
+    }
+
+    pub fn find(&self, kid: &str) -> Option<Jwk> {
+        self.keys.read().unwrap().find(kid).cloned()
+    }
+
+    /// Look up `kid`, refetching the set once if it is unknown, since the
+    /// issuer may have rotated in a new key since the last refresh
+    pub async fn find_or_refresh(&self, kid: &str) -> Option<Jwk> {
+        if let Some(jwk) = self.find(kid) {
+            return Some(jwk);
+        }
+
+        let mut last_fetch = self.last_fetch.lock().await;
+        // Another request may have refetched while we waited for the lock
+        if last_fetch.is_some_and(|at| at.elapsed() < MIN_REFETCH_INTERVAL) {
+            return self.find(kid);
+        }
+
+        debug!("Unknown JWT kid {}, refetching {}", kid, self.url);
+        if let Err(e) = self.refresh_locked(&mut last_fetch).await {
+            warn!("{:#}", e);
+        }
+        self.find(kid)
+    }
+
+    /// Refresh on a fixed interval, starting immediately
+    pub async fn run(self: Arc<Self>, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+
+        loop {
+            ticker.tick().await;
+            match self.refresh().await {
+                Ok(count) => debug!("Loaded {} keys from {}", count, self.url),
+                Err(e) => warn!("{:#}", e),
+            }
+        }
+    }
+}
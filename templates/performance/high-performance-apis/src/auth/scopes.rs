@@ -0,0 +1,128 @@
+use axum::{
+    http::{header::WWW_AUTHENTICATE, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use super::Principal;
+use crate::problem::ProblemDetails;
+
+/// Whether a granted scope covers `required`: an exact match, a
+/// `resource:*` wildcard, or `*`
+pub fn scope_matches(granted: &str, required: &str) -> bool {
+    if granted == "*" || granted == required {
+        return true;
+    }
+
+    match (granted.strip_suffix(":*"), required.split_once(':')) {
+        (Some(resource), Some((required_resource, _))) => resource == required_resource,
+        _ => false,
+    }
+}
+
+impl Principal {
+    /// Scopes from the token's `scope` and `permissions` claims, the API
+    /// key's `permissions`, or those `server.tls.client_scopes` grants the
+    /// certificate
+    pub fn scopes(&self) -> Vec<&str> {
+        match self {
+            Self::Token(claims) => claims
+                .scope
+                .iter()
+                .flat_map(|scope| scope.split_whitespace())
+                .chain(claims.permissions.iter().map(String::as_str))
+                .collect(),
+            Self::ApiKey(identity) => identity.permissions.iter().map(String::as_str).collect(),
+            Self::Certificate(certificate) => certificate.scopes.iter().map(String::as_str).collect(),
+        }
+    }
+
+    pub fn has_scope(&self, required: &str) -> bool {
+        self.scopes().iter().any(|granted| scope_matches(granted, required))
+    }
+}
+
+/// The caller lacks scopes a route requires
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("missing required scope {}", .missing.join(" "))]
+pub struct ScopeError {
+    pub missing: Vec<String>,
+}
+
+/// Require every scope in `required`
+pub fn check_scopes<S: AsRef<str>>(principal: &Principal, required: &[S]) -> Result<(), ScopeError> {
+    let missing: Vec<String> = required
+        .iter()
+        .map(AsRef::as_ref)
+        .filter(|scope| !principal.has_scope(scope))
+        .map(str::to_string)
+        .collect();
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(ScopeError { missing })
+    }
+}
+
+impl IntoResponse for ScopeError {
+    fn into_response(self) -> Response {
+        let challenge = format!(r#"Bearer error="insufficient_scope", scope="{}""#, self.missing.join(" "));
+
+        let mut response = ProblemDetails::new(StatusCode::FORBIDDEN)
+            .with_detail(format!("This request requires the {} scope", self.missing.join(", ")))
+            .with_extension("required_scopes", self.missing)
+            .into_response();
+        if let Ok(challenge) = HeaderValue::from_str(&challenge) {
+            response.headers_mut().insert(WWW_AUTHENTICATE, challenge);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{api_keys::ApiKeyIdentity, Claims};
+
+    fn token(scope: &str, permissions: &[&str]) -> Principal {
+        Principal::Token(Claims {
+            sub: "admin".to_string(),
+            exp: u64::MAX,
+            iat: 0,
+            iss: None,
+            aud: None,
+            scope: Some(scope.to_string()),
+            permissions: permissions.iter().map(|p| p.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn test_scope_wildcards() {
+        assert!(scope_matches("admin:read", "admin:read"));
+        assert!(scope_matches("admin:*", "admin:read"));
+        assert!(scope_matches("*", "metrics:read"));
+        assert!(!scope_matches("admin:*", "administrator:read"));
+        assert!(!scope_matches("admin:read", "admin:write"));
+        assert!(!scope_matches("admin", "admin:read"));
+    }
+
+    #[test]
+    fn test_scopes_from_claims_and_api_keys() {
+        let principal = token("openid admin:read", &["metrics:read"]);
+        assert!(check_scopes(&principal, &["admin:read", "metrics:read"]).is_ok());
+        assert_eq!(
+            check_scopes(&principal, &["admin:read", "api_keys:write"]).unwrap_err().missing,
+            vec!["api_keys:write".to_string()]
+        );
+
+        let principal = Principal::ApiKey(ApiKeyIdentity {
+            id: uuid::Uuid::new_v4(),
+            user_id: uuid::Uuid::new_v4(),
+            permissions: vec!["plan:pro".to_string(), "metrics:*".to_string()],
+            expires_at: None,
+            revoked_at: None,
+        });
+        assert!(principal.has_scope("metrics:read"));
+        assert!(!principal.has_scope("admin:read"));
+    }
+}
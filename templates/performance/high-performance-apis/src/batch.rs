@@ -0,0 +1,117 @@
+use axum::async_trait;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// Where a [`BatchWriter`] writes what it drains from its queue
+#[async_trait]
+pub trait BatchSink: Send + Sync {
+    type Item: Send + Sync;
+
+    async fn write(&self, batch: &[Self::Item]) -> anyhow::Result<()>;
+
+    /// Count items written, and items dropped because their batch failed
+    fn record(&self, written: u64, dropped: u64);
+}
+
+/// Drains a bounded queue into a [`BatchSink`] in the background, so
+/// request handlers only ever `try_send`
+pub struct BatchWriter<S: BatchSink> {
+    sink: S,
+    receiver: mpsc::Receiver<S::Item>,
+    batch_size: usize,
+    flush_interval: Duration,
+}
+
+impl<S: BatchSink> BatchWriter<S> {
+    pub fn new(sink: S, receiver: mpsc::Receiver<S::Item>, batch_size: usize, flush_interval: Duration) -> Self {
+        Self {
+            sink,
+            receiver,
+            batch_size,
+            flush_interval,
+        }
+    }
+
+    /// Write items until every sender is dropped. A batch is written when it
+    /// is full or `flush_interval` after its first item.
+    pub async fn run(mut self) {
+        let mut batch = Vec::with_capacity(self.batch_size);
+
+        while let Some(item) = self.receiver.recv().await {
+            batch.push(item);
+
+            let deadline = tokio::time::sleep(self.flush_interval);
+            tokio::pin!(deadline);
+            while batch.len() < self.batch_size {
+                tokio::select! {
+                    item = self.receiver.recv() => match item {
+                        Some(item) => batch.push(item),
+                        None => break,
+                    },
+                    _ = &mut deadline => break,
+                }
+            }
+
+            let count = batch.len() as u64;
+            match self.sink.write(&batch).await {
+                Ok(()) => self.sink.record(count, 0),
+                Err(e) => {
+                    self.sink.record(0, count);
+                    warn!("{:#}", e);
+                }
+            }
+            batch.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Collected {
+        batches: Arc<Mutex<Vec<Vec<u32>>>>,
+        written: Arc<Mutex<(u64, u64)>>,
+    }
+
+    #[async_trait]
+    impl BatchSink for Collected {
+        type Item = u32;
+
+        async fn write(&self, batch: &[u32]) -> anyhow::Result<()> {
+            if batch.contains(&0) {
+                anyhow::bail!("rejected batch");
+            }
+            self.batches.lock().unwrap().push(batch.to_vec());
+            Ok(())
+        }
+
+        fn record(&self, written: u64, dropped: u64) {
+            let mut totals = self.written.lock().unwrap();
+            totals.0 += written;
+            totals.1 += dropped;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batches_flush_when_full_or_due() {
+        let sink = Collected::default();
+        let (sender, receiver) = mpsc::channel(16);
+        let writer = tokio::spawn(BatchWriter::new(sink.clone(), receiver, 3, Duration::from_millis(20)).run());
+
+        for item in 1..=4 {
+            sender.send(item).await.unwrap();
+        }
+        // The partial batch goes out once the flush interval passes
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        sender.send(0).await.unwrap();
+        drop(sender);
+        writer.await.unwrap();
+
+        assert_eq!(*sink.batches.lock().unwrap(), vec![vec![1, 2, 3], vec![4]]);
+        assert_eq!(*sink.written.lock().unwrap(), (4, 1));
+    }
+}
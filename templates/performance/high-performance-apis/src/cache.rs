@@ -0,0 +1,960 @@
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{OriginalUri, Request},
+    http::{
+        header::{AGE, AUTHORIZATION, CACHE_CONTROL, ETAG, IF_NONE_MATCH, SET_COOKIE},
+        HeaderMap, HeaderName, HeaderValue, Method, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use moka::future::Cache;
+use redis::{AsyncCommands, Script};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    convert::Infallible,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tower::{Layer, Service};
+use tracing::{debug, info, warn};
+
+use crate::{
+    auth::Principal,
+    config::{CacheConfig, RedisConfig},
+    error::AppError,
+    metrics::{CacheResult, Metrics},
+};
+
+/// Says where a response came from: `HIT-L1`, `HIT-L2` or `MISS`
+const X_CACHE: HeaderName = HeaderName::from_static("x-cache");
+
+/// How often replicas waiting on another's fill look for its entry
+const FILL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Headers the cache sets itself when serving an entry
+const OWN_HEADERS: [HeaderName; 3] = [CACHE_CONTROL, AGE, ETAG];
+
+/// Store an entry and index it under each tag. The tag sets live as long as
+/// their longest-lived entry.
+const STORE_SCRIPT: &str = r#"
+local ttl = tonumber(ARGV[2])
+redis.call('SET', KEYS[1], ARGV[1], 'EX', ttl)
+for i = 2, #KEYS do
+    redis.call('SADD', KEYS[i], KEYS[1])
+    if redis.call('TTL', KEYS[i]) < ttl then
+        redis.call('EXPIRE', KEYS[i], ttl)
+    end
+end
+return 1
+"#;
+
+/// Delete every entry indexed under the given tags, and the tag sets
+const INVALIDATE_SCRIPT: &str = r#"
+local removed = 0
+for i = 1, #KEYS do
+    for _, entry in ipairs(redis.call('SMEMBERS', KEYS[i])) do
+        removed = removed + redis.call('DEL', entry)
+    end
+    redis.call('DEL', KEYS[i])
+end
+return removed
+"#;
+
+/// Release a fill lock, unless it expired and another replica took it
+const UNLOCK_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+/// A buffered `200` response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedResponse {
+    headers: Vec<(String, String)>,
+    etag: String,
+    tags: Vec<String>,
+    stored_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    #[serde(with = "base64_body")]
+    body: Bytes,
+}
+
+impl CachedResponse {
+    fn is_fresh(&self) -> bool {
+        self.expires_at > Utc::now()
+    }
+
+    /// The stored response, or a `304` when `If-None-Match` names its ETag.
+    /// `private` keeps shared caches downstream from storing it.
+    fn to_response(&self, if_none_match: Option<&HeaderValue>, private: bool) -> Response {
+        let now = Utc::now();
+        let max_age = (self.expires_at - now).num_seconds().max(0);
+        let age = (now - self.stored_at).num_seconds().max(0);
+        let visibility = if private { "private" } else { "public" };
+
+        let mut response = if if_none_match.is_some_and(|value| etag_matches(value, &self.etag)) {
+            StatusCode::NOT_MODIFIED.into_response()
+        } else {
+            let mut response = Response::new(Body::from(self.body.clone()));
+            let headers = response.headers_mut();
+            for (name, value) in &self.headers {
+                if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+                    headers.append(name, value);
+                }
+            }
+            response
+        };
+
+        let headers = response.headers_mut();
+        if let Ok(etag) = HeaderValue::try_from(&self.etag) {
+            headers.insert(ETAG, etag);
+        }
+        if let Ok(cache_control) = HeaderValue::try_from(format!("{}, max-age={}", visibility, max_age)) {
+            headers.insert(CACHE_CONTROL, cache_control);
+        }
+        headers.insert(AGE, HeaderValue::from(age));
+        response
+    }
+}
+
+mod base64_body {
+    use axum::body::Bytes;
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(body: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64.encode(body))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        BASE64.decode(encoded).map(Bytes::from).map_err(serde::de::Error::custom)
+    }
+}
+
+/// The handler's response could not be cached, so it goes back to the caller
+#[derive(Debug)]
+struct Uncacheable;
+
+enum FillLock {
+    /// This replica fills the entry, and releases the lock with the token
+    Held(String),
+    /// Another replica is filling it
+    Contended,
+    /// Redis is down; every replica fills for itself
+    Unavailable,
+}
+
+/// Two-tier cache for GET responses: an in-process L1 in front of Redis,
+/// shared by every replica. Entries are tagged so write handlers can drop
+/// everything they affect with [`ResponseCache::invalidate`]; other
+/// replicas hear about it over Redis pub/sub. Concurrent misses for the same
+/// key are coalesced in process, and across replicas by a lock in Redis.
+#[derive(Clone)]
+pub struct ResponseCache {
+    l1: Cache<String, Arc<CachedResponse>>,
+    redis: deadpool_redis::Pool,
+    /// Pub/sub needs a dedicated connection outside the pool
+    client: redis::Client,
+    config: Arc<CacheConfig>,
+    metrics: Metrics,
+    /// Bumped on every invalidation; fills that straddle one aren't stored
+    generation: Arc<AtomicU64>,
+    store_script: Arc<Script>,
+    invalidate_script: Arc<Script>,
+    unlock_script: Arc<Script>,
+}
+
+impl ResponseCache {
+    pub fn new(
+        redis: deadpool_redis::Pool,
+        redis_config: &RedisConfig,
+        config: &CacheConfig,
+        metrics: Metrics,
+    ) -> anyhow::Result<Self> {
+        let l1 = Cache::builder()
+            .max_capacity(config.l1_max_entries)
+            .time_to_live(config.l1_ttl)
+            .support_invalidation_closures()
+            .build();
+
+        Ok(Self {
+            l1,
+            redis,
+            client: redis::Client::open(redis_config.url.as_str())?,
+            config: Arc::new(config.clone()),
+            metrics,
+            generation: Arc::new(AtomicU64::new(0)),
+            store_script: Arc::new(Script::new(STORE_SCRIPT)),
+            invalidate_script: Arc::new(Script::new(INVALIDATE_SCRIPT)),
+            unlock_script: Arc::new(Script::new(UNLOCK_SCRIPT)),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Drop every response tagged with any of `tags`, here and on every
+    /// other replica. Call after the write commits; failures are logged,
+    /// never returned, since the write itself succeeded.
+    pub async fn invalidate(&self, tags: &[&str]) {
+        if !self.enabled() || tags.is_empty() {
+            return;
+        }
+
+        self.invalidate_local(tags.iter().map(|tag| tag.to_string()).collect());
+        self.metrics.record_cache_invalidation(tags.len() as u64);
+        if let Err(e) = self.invalidate_shared(tags).await {
+            warn!("Failed to invalidate cached responses tagged {}: {}", tags.join(", "), e);
+        }
+    }
+
+    async fn invalidate_shared(&self, tags: &[&str]) -> anyhow::Result<()> {
+        let mut conn = self.redis.get().await?;
+        let mut invocation = self.invalidate_script.prepare_invoke();
+        for tag in tags {
+            invocation.key(self.tag_key(tag));
+        }
+        let removed: u64 = invocation.invoke_async(&mut conn).await?;
+        debug!("Invalidated {} cached responses tagged {}", removed, tags.join(", "));
+
+        let _: u64 = conn
+            .publish(self.invalidations_channel(), serde_json::to_string(tags)?)
+            .await?;
+        Ok(())
+    }
+
+    fn invalidate_local(&self, tags: Vec<String>) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        let invalidated = self
+            .l1
+            .invalidate_entries_if(move |_, entry| entry.tags.iter().any(|tag| tags.contains(tag)));
+        if let Err(e) = invalidated {
+            warn!("Failed to invalidate in-process cached responses: {}", e);
+        }
+    }
+
+    /// Drop in-process entries when another replica invalidates tags.
+    /// Entries also expire after `cache.l1_ttl`, so a missed message only
+    /// serves stale responses that long.
+    pub async fn listen_for_invalidations(self) {
+        loop {
+            match self.client.get_async_connection().await {
+                Ok(conn) => {
+                    let mut pubsub = conn.into_pubsub();
+                    if let Err(e) = pubsub.subscribe(self.invalidations_channel()).await {
+                        warn!("Failed to subscribe to cache invalidations: {}", e);
+                    } else {
+                        info!("Listening for cache invalidations");
+                        self.l1.invalidate_all();
+
+                        let mut messages = pubsub.on_message();
+                        while let Some(message) = messages.next().await {
+                            let tags = message
+                                .get_payload::<String>()
+                                .map_err(anyhow::Error::from)
+                                .and_then(|payload| Ok(serde_json::from_str::<Vec<String>>(&payload)?));
+                            match tags {
+                                Ok(tags) => self.invalidate_local(tags),
+                                Err(e) => warn!("Ignoring malformed cache invalidation: {}", e),
+                            }
+                        }
+                        warn!("Cache invalidation subscription closed");
+                    }
+                }
+                Err(e) => warn!("Failed to connect cache invalidation listener: {}", e),
+            }
+
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    /// Serve `request` from the cache, filling it from `inner` on a miss.
+    /// The client's `Cache-Control` is ignored, so no caller can skip the
+    /// cache or the single fill; `If-None-Match` revalidates instead.
+    async fn serve<S>(&self, request: Request, tags: &[String], inner: &mut S) -> Response
+    where
+        S: Service<Request, Response = Response, Error = Infallible> + Send,
+        S::Future: Send,
+    {
+        if request.method() != Method::GET {
+            self.metrics.record_cache(CacheResult::Bypass);
+            return call(inner, request).await;
+        }
+
+        let key = request_key(&request);
+        let private = has_credentials(&request);
+        let if_none_match = request.headers().get(IF_NONE_MATCH).cloned();
+
+        if let Some(entry) = self.l1.get(&key).await {
+            if entry.is_fresh() {
+                self.metrics.record_cache(CacheResult::L1Hit);
+                return served(&entry, if_none_match.as_ref(), private, CacheResult::L1Hit);
+            }
+            self.l1.invalidate(&key).await;
+        }
+
+        // Only one request per key runs the fill below; the others wait for
+        // it and share the entry. If the response can't be cached they call
+        // the handler themselves, so each keeps its own request until then.
+        let fill = Arc::new(Mutex::new(Fill {
+            request: Some(request),
+            result: CacheResult::L1Hit,
+            uncached: None,
+        }));
+        let entry = self
+            .l1
+            .try_get_with(key.clone(), self.fill(&key, tags, private, fill.clone(), inner))
+            .await;
+
+        let (result, uncached, request) = {
+            let mut fill = fill.lock().unwrap();
+            (fill.result, fill.uncached.take(), fill.request.take())
+        };
+        match (entry, uncached, request) {
+            (Ok(entry), _, _) => {
+                self.metrics.record_cache(result);
+                served(&entry, if_none_match.as_ref(), private, result)
+            }
+            (Err(_), Some(response), _) => {
+                self.metrics.record_cache(CacheResult::Bypass);
+                response
+            }
+            (Err(_), None, Some(request)) => {
+                self.metrics.record_cache(CacheResult::Bypass);
+                call(inner, request).await
+            }
+            (Err(_), None, None) => unreachable!("a failed fill returns the response it took the request for"),
+        }
+    }
+
+    /// Look the entry up in Redis, or call the handler and store what it
+    /// returns. Other replicas filling the same key wait for this one.
+    async fn fill<S>(
+        &self,
+        key: &str,
+        tags: &[String],
+        private: bool,
+        fill: Arc<Mutex<Fill>>,
+        inner: &mut S,
+    ) -> Result<Arc<CachedResponse>, Uncacheable>
+    where
+        S: Service<Request, Response = Response, Error = Infallible> + Send,
+        S::Future: Send,
+    {
+        if let Some(entry) = self.l2_get(key).await {
+            fill.lock().unwrap().result = CacheResult::L2Hit;
+            return Ok(Arc::new(entry));
+        }
+
+        let lock = self.lock(key).await;
+        if let FillLock::Contended = lock {
+            if let Some(entry) = self.wait_for_fill(key).await {
+                fill.lock().unwrap().result = CacheResult::L2Hit;
+                return Ok(Arc::new(entry));
+            }
+        }
+
+        let generation = self.generation.load(Ordering::SeqCst);
+        let request = fill.lock().unwrap().request.take().expect("the fill runs once per request");
+        let response = call(inner, request).await;
+        let buffered = buffer(response, tags, &self.config).await;
+
+        let stored = match buffered {
+            // A write invalidated these tags while the handler ran, so the
+            // response may predate it
+            Ok(entry) if self.generation.load(Ordering::SeqCst) != generation => {
+                Err(entry.to_response(None, private))
+            }
+            Ok(entry) => {
+                self.l2_store(key, &entry).await;
+                Ok(entry)
+            }
+            Err(response) => Err(response),
+        };
+        if let FillLock::Held(token) = lock {
+            self.unlock(key, &token).await;
+        }
+
+        match stored {
+            Ok(entry) => {
+                fill.lock().unwrap().result = CacheResult::Miss;
+                Ok(Arc::new(entry))
+            }
+            Err(response) => {
+                fill.lock().unwrap().uncached = Some(response);
+                Err(Uncacheable)
+            }
+        }
+    }
+
+    async fn l2_get(&self, key: &str) -> Option<CachedResponse> {
+        let lookup = async {
+            let mut conn = self.redis.get().await?;
+            let payload: Option<String> = conn.get(self.entry_key(key)).await?;
+            anyhow::Ok(payload.map(|payload| serde_json::from_str::<CachedResponse>(&payload)).transpose()?)
+        };
+
+        match lookup.await {
+            Ok(entry) => entry.filter(CachedResponse::is_fresh),
+            Err(e) => {
+                debug!("Cached response lookup failed: {}", e);
+                None
+            }
+        }
+    }
+
+    async fn l2_store(&self, key: &str, entry: &CachedResponse) {
+        let ttl = (entry.expires_at - entry.stored_at).num_seconds().max(1);
+        let store = async {
+            let mut conn = self.redis.get().await?;
+            let mut invocation = self.store_script.prepare_invoke();
+            invocation.key(self.entry_key(key));
+            for tag in &entry.tags {
+                invocation.key(self.tag_key(tag));
+            }
+            let _: i64 = invocation
+                .arg(serde_json::to_string(entry)?)
+                .arg(ttl)
+                .invoke_async(&mut conn)
+                .await?;
+            anyhow::Ok(())
+        };
+
+        if let Err(e) = store.await {
+            debug!("Failed to store cached response: {}", e);
+        }
+    }
+
+    async fn lock(&self, key: &str) -> FillLock {
+        let token = uuid::Uuid::new_v4().to_string();
+        let acquire = async {
+            let mut conn = self.redis.get().await?;
+            let acquired: Option<String> = redis::cmd("SET")
+                .arg(self.lock_key(key))
+                .arg(&token)
+                .arg("NX")
+                .arg("PX")
+                .arg(self.config.fill_lock_ttl.as_millis() as u64)
+                .query_async(&mut conn)
+                .await?;
+            anyhow::Ok(acquired.is_some())
+        };
+
+        match acquire.await {
+            Ok(true) => FillLock::Held(token),
+            Ok(false) => FillLock::Contended,
+            Err(e) => {
+                debug!("Failed to take cache fill lock: {}", e);
+                FillLock::Unavailable
+            }
+        }
+    }
+
+    async fn unlock(&self, key: &str, token: &str) {
+        let release = async {
+            let mut conn = self.redis.get().await?;
+            let _: i64 = self
+                .unlock_script
+                .key(self.lock_key(key))
+                .arg(token)
+                .invoke_async(&mut conn)
+                .await?;
+            anyhow::Ok(())
+        };
+
+        if let Err(e) = release.await {
+            debug!("Failed to release cache fill lock: {}", e);
+        }
+    }
+
+    /// Poll for the entry another replica is filling, until its lock is
+    /// released or expires
+    async fn wait_for_fill(&self, key: &str) -> Option<CachedResponse> {
+        let deadline = tokio::time::Instant::now() + self.config.fill_lock_ttl;
+        while tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(FILL_POLL_INTERVAL).await;
+            if let Some(entry) = self.l2_get(key).await {
+                return Some(entry);
+            }
+
+            let locked = async {
+                let mut conn = self.redis.get().await?;
+                anyhow::Ok(conn.exists::<_, bool>(self.lock_key(key)).await?)
+            };
+            if !locked.await.unwrap_or(false) {
+                break;
+            }
+        }
+        None
+    }
+
+    fn entry_key(&self, key: &str) -> String {
+        format!("{}entry:{}", self.config.redis_key_prefix, key)
+    }
+
+    fn tag_key(&self, tag: &str) -> String {
+        format!("{}tag:{}", self.config.redis_key_prefix, tag)
+    }
+
+    fn lock_key(&self, key: &str) -> String {
+        format!("{}lock:{}", self.config.redis_key_prefix, key)
+    }
+
+    fn invalidations_channel(&self) -> String {
+        format!("{}invalidate", self.config.redis_key_prefix)
+    }
+}
+
+/// State shared between a request and the fill that may run for it
+struct Fill {
+    request: Option<Request>,
+    result: CacheResult,
+    /// The response taken from the handler when it couldn't be cached
+    uncached: Option<Response>,
+}
+
+async fn call<S>(inner: &mut S, request: Request) -> Response
+where
+    S: Service<Request, Response = Response, Error = Infallible>,
+{
+    match inner.call(request).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
+
+fn served(entry: &CachedResponse, if_none_match: Option<&HeaderValue>, private: bool, result: CacheResult) -> Response {
+    let mut response = entry.to_response(if_none_match, private);
+    let x_cache = match result {
+        CacheResult::L1Hit => "HIT-L1",
+        CacheResult::L2Hit => "HIT-L2",
+        CacheResult::Miss | CacheResult::Bypass => "MISS",
+    };
+    response.headers_mut().insert(X_CACHE, HeaderValue::from_static(x_cache));
+    response
+}
+
+/// Buffer a cacheable response into an entry, or hand it back untouched
+async fn buffer(response: Response, tags: &[String], config: &CacheConfig) -> Result<CachedResponse, Response> {
+    let Some(ttl) = response_ttl(&response, config.default_ttl) else {
+        return Err(response);
+    };
+    let fits = matches!(response.body().size_hint().upper(), Some(size) if size <= config.max_body_bytes as u64);
+    if !fits {
+        return Err(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, config.max_body_bytes).await {
+        Ok(body) => body,
+        Err(e) => return Err(AppError::Internal(anyhow::anyhow!("failed to buffer response: {}", e)).into_response()),
+    };
+
+    let etag = parts
+        .headers
+        .get(ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("\"{}\"", &hex::encode(Sha256::digest(&body))[..32]));
+    let headers = parts
+        .headers
+        .iter()
+        .filter(|(name, _)| !OWN_HEADERS.contains(*name))
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+
+    let stored_at = Utc::now();
+    Ok(CachedResponse {
+        headers,
+        etag,
+        tags: tags.to_vec(),
+        stored_at,
+        expires_at: stored_at + chrono::Duration::from_std(ttl).unwrap_or_else(|_| chrono::Duration::zero()),
+        body,
+    })
+}
+
+/// How long a response may be cached: the handler's `max-age` or
+/// `default_ttl`. Only `200`s without cookies or `no-store`, `no-cache` or
+/// `private` are cached.
+fn response_ttl(response: &Response, default_ttl: Duration) -> Option<Duration> {
+    if response.status() != StatusCode::OK || response.headers().contains_key(SET_COOKIE) {
+        return None;
+    }
+
+    let mut ttl = default_ttl;
+    for directive in cache_directives(response.headers()) {
+        match directive.split_once('=') {
+            Some(("max-age", seconds)) => ttl = Duration::from_secs(seconds.trim_matches('"').parse().ok()?),
+            None if matches!(directive.as_str(), "no-store" | "no-cache" | "private") => return None,
+            _ => {}
+        }
+    }
+    (!ttl.is_zero()).then_some(ttl)
+}
+
+fn cache_directives(headers: &HeaderMap) -> impl Iterator<Item = String> + '_ {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .filter(|directive| !directive.is_empty())
+}
+
+/// Whether an `If-None-Match` value names `etag`, comparing weakly as
+/// RFC 9110 asks for GET
+fn etag_matches(if_none_match: &HeaderValue, etag: &str) -> bool {
+    let Ok(if_none_match) = if_none_match.to_str() else {
+        return false;
+    };
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+
+    if_none_match
+        .split(',')
+        .any(|candidate| candidate.trim() == "*" || opaque(candidate) == opaque(etag))
+}
+
+fn has_credentials(request: &Request) -> bool {
+    request.extensions().get::<Principal>().is_some()
+        || request.headers().contains_key(AUTHORIZATION)
+        || request.headers().contains_key("x-api-key")
+}
+
+/// The cache key of a request: its route, query and whoever may see the
+/// response. Callers with the same scopes share entries; requests that
+/// carry credentials no middleware has checked are keyed by the credentials.
+fn request_key(request: &Request) -> String {
+    let uri = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|original| &original.0)
+        .unwrap_or_else(|| request.uri());
+
+    let partition = match request.extensions().get::<Principal>() {
+        Some(principal) => {
+            let mut scopes = principal.scopes();
+            scopes.sort_unstable();
+            scopes.dedup();
+            format!("scopes:{}", scopes.join(" "))
+        }
+        None if has_credentials(request) => {
+            let mut credentials = Sha256::new();
+            for name in [AUTHORIZATION.as_str(), "x-api-key"] {
+                if let Some(value) = request.headers().get(name) {
+                    credentials.update(value.as_bytes());
+                }
+                credentials.update([0u8]);
+            }
+            format!("credentials:{}", hex::encode(credentials.finalize()))
+        }
+        None => "anonymous".to_string(),
+    };
+
+    cache_key(uri.path(), uri.query(), &partition)
+}
+
+/// Query parameters are sorted, so their order doesn't split entries
+fn cache_key(path: &str, query: Option<&str>, partition: &str) -> String {
+    let mut params: Vec<&str> = query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .collect();
+    params.sort_unstable();
+
+    let mut key = Sha256::new();
+    for part in [path, &params.join("&"), partition] {
+        key.update(part.as_bytes());
+        key.update([0u8]);
+    }
+    hex::encode(key.finalize())
+}
+
+/// Caches a route's GET responses in a [`ResponseCache`] under the given
+/// tags. Attach it to single routes, inside any `RequireScopeLayer`, so
+/// only authorized requests reach the cache:
+///
+/// ```ignore
+/// get(list_keys)
+///     .layer(CacheLayer::new(cache, ["api_keys"]))
+///     .layer(RequireScopeLayer::new(["api_keys:read"]))
+/// ```
+///
+/// Responses carry an ETag, `Cache-Control` with the remaining lifetime,
+/// `Age` and `x-cache`; `If-None-Match` is answered with `304`.
+#[derive(Clone)]
+pub struct CacheLayer {
+    cache: ResponseCache,
+    tags: Arc<[String]>,
+}
+
+impl CacheLayer {
+    pub fn new<I, S>(cache: ResponseCache, tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            cache,
+            tags: tags.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl<S> Layer<S> for CacheLayer {
+    type Service = CacheService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CacheService {
+            inner,
+            cache: self.cache.clone(),
+            tags: self.tags.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CacheService<S> {
+    inner: S,
+    cache: ResponseCache,
+    tags: Arc<[String]>,
+}
+
+impl<S> Service<Request> for CacheService<S>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = std::pin::Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        if !self.cache.enabled() {
+            return Box::pin(self.inner.call(request));
+        }
+
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let cache = self.cache.clone();
+        let tags = self.tags.clone();
+
+        Box::pin(async move { Ok(cache.serve(request, &tags, &mut inner).await) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{auth::Claims, config::Config};
+    use axum::{routing::get, Json, Router};
+    use std::sync::atomic::AtomicUsize;
+    use tower::ServiceExt;
+
+    fn principal(scope: &str) -> Principal {
+        Principal::Token(Claims {
+            sub: "user".to_string(),
+            exp: u64::MAX,
+            iat: 0,
+            iss: None,
+            aud: None,
+            scope: Some(scope.to_string()),
+            permissions: Vec::new(),
+        })
+    }
+
+    fn request(uri: &str) -> Request {
+        Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
+    /// A cache whose Redis is unreachable, so only L1 answers
+    fn cache() -> ResponseCache {
+        let config = Config::default();
+        let redis = deadpool_redis::Config::from_url("redis://127.0.0.1:1")
+            .create_pool(Some(deadpool_redis::Runtime::Tokio1))
+            .unwrap();
+        let redis_config = RedisConfig {
+            url: "redis://127.0.0.1:1".to_string(),
+            ..config.redis.clone()
+        };
+        ResponseCache::new(redis, &redis_config, &config.cache, Metrics::disabled()).unwrap()
+    }
+
+    fn counting_router(cache: ResponseCache, calls: Arc<AtomicUsize>) -> Router {
+        let handler = move || {
+            let calls = calls.clone();
+            async move {
+                let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Json(serde_json::json!({ "call": call }))
+            }
+        };
+        Router::new().route("/items", get(handler).layer(CacheLayer::new(cache, ["items"])))
+    }
+
+    #[test]
+    fn test_keys_follow_route_query_and_scopes() {
+        let key = |uri: &str, scope: Option<&str>| {
+            let mut request = request(uri);
+            if let Some(scope) = scope {
+                request.extensions_mut().insert(principal(scope));
+            }
+            request_key(&request)
+        };
+
+        assert_eq!(key("/items?a=1&b=2", None), key("/items?b=2&a=1", None));
+        assert_ne!(key("/items?a=1", None), key("/items?a=2", None));
+        assert_ne!(key("/items", None), key("/other", None));
+
+        // Scope order doesn't matter; the scopes themselves do
+        assert_eq!(key("/items", Some("a:read b:read")), key("/items", Some("b:read a:read")));
+        assert_ne!(key("/items", Some("a:read")), key("/items", Some("a:read b:read")));
+        assert_ne!(key("/items", Some("a:read")), key("/items", None));
+
+        // Unchecked credentials never share the anonymous entry
+        let with_key = |value: &'static str| {
+            let mut request = request("/items");
+            request.headers_mut().insert("x-api-key", HeaderValue::from_static(value));
+            request_key(&request)
+        };
+        assert_ne!(with_key("hpk_a"), key("/items", None));
+        assert_ne!(with_key("hpk_a"), with_key("hpk_b"));
+    }
+
+    #[test]
+    fn test_etags_match_lists_weak_tags_and_wildcards() {
+        let etag = "\"abc\"";
+        for value in ["\"abc\"", "W/\"abc\"", "\"xyz\", \"abc\"", "*"] {
+            assert!(etag_matches(&HeaderValue::from_static(value), etag), "{}", value);
+        }
+        assert!(!etag_matches(&HeaderValue::from_static("\"xyz\""), etag));
+    }
+
+    #[test]
+    fn test_only_cacheable_responses_get_a_ttl() {
+        let default = Duration::from_secs(60);
+        let with = |status: StatusCode, headers: &[(HeaderName, &'static str)]| {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = status;
+            for (name, value) in headers {
+                response.headers_mut().insert(name.clone(), HeaderValue::from_static(value));
+            }
+            response_ttl(&response, default)
+        };
+
+        assert_eq!(with(StatusCode::OK, &[]), Some(default));
+        assert_eq!(with(StatusCode::OK, &[(CACHE_CONTROL, "max-age=5")]), Some(Duration::from_secs(5)));
+        assert_eq!(with(StatusCode::OK, &[(CACHE_CONTROL, "max-age=0")]), None);
+        assert_eq!(with(StatusCode::OK, &[(CACHE_CONTROL, "no-store")]), None);
+        assert_eq!(with(StatusCode::OK, &[(SET_COOKIE, "session=1")]), None);
+        assert_eq!(with(StatusCode::NOT_FOUND, &[]), None);
+    }
+
+    #[tokio::test]
+    async fn test_hits_revalidate_and_invalidate() {
+        let cache = cache();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = counting_router(cache.clone(), calls.clone());
+
+        let first = app.clone().oneshot(request("/items")).await.unwrap();
+        assert_eq!(first.headers()[X_CACHE], "MISS");
+        let etag = first.headers()[ETAG].clone();
+        assert!(first.headers()[CACHE_CONTROL].to_str().unwrap().starts_with("public, max-age="));
+
+        let second = app.clone().oneshot(request("/items")).await.unwrap();
+        assert_eq!(second.headers()[X_CACHE], "HIT-L1");
+        assert_eq!(second.headers()[ETAG], etag);
+        let body = axum::body::to_bytes(second.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], br#"{"call":1}"#);
+
+        let mut conditional = request("/items");
+        conditional.headers_mut().insert(IF_NONE_MATCH, etag);
+        let not_modified = app.clone().oneshot(conditional).await.unwrap();
+        assert_eq!(not_modified.status(), StatusCode::NOT_MODIFIED);
+        assert!(not_modified.headers().contains_key(ETAG));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        cache.invalidate(&["items"]).await;
+        let refilled = app.clone().oneshot(request("/items")).await.unwrap();
+        assert_eq!(refilled.headers()[X_CACHE], "MISS");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Tags that don't match leave the entry alone
+        cache.invalidate(&["other"]).await;
+        let hit = app.oneshot(request("/items")).await.unwrap();
+        assert_eq!(hit.headers()[X_CACHE], "HIT-L1");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_misses_fill_once() {
+        let cache = cache();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = counting_router(cache, calls.clone());
+
+        let responses =
+            futures::future::join_all((0..10).map(|_| app.clone().oneshot(request("/items?page=1")))).await;
+        assert!(responses.iter().all(|response| response.as_ref().unwrap().status() == StatusCode::OK));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_clients_cannot_skip_the_cache() {
+        let cache = cache();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = counting_router(cache, calls.clone());
+        let uncached = || {
+            let mut request = request("/items");
+            request
+                .headers_mut()
+                .insert(CACHE_CONTROL, HeaderValue::from_static("no-cache, no-store, max-age=0"));
+            request
+        };
+
+        let responses = futures::future::join_all((0..10).map(|_| app.clone().oneshot(uncached()))).await;
+        assert!(responses.iter().all(|response| response.as_ref().unwrap().status() == StatusCode::OK));
+        let hit = app.oneshot(uncached()).await.unwrap();
+        assert_eq!(hit.headers()[X_CACHE], "HIT-L1");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fills_straddling_an_invalidation_stay_private() {
+        let cache = cache();
+        let writer = cache.clone();
+        let handler = move || {
+            let writer = writer.clone();
+            async move {
+                writer.invalidate(&["items"]).await;
+                Json(serde_json::json!({ "call": 1 }))
+            }
+        };
+        let app = Router::new().route("/items", get(handler).layer(CacheLayer::new(cache, ["items"])));
+
+        let mut credentialed = request("/items");
+        credentialed
+            .headers_mut()
+            .insert(AUTHORIZATION, HeaderValue::from_static("Bearer token"));
+        let response = app.oneshot(credentialed).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()[CACHE_CONTROL].to_str().unwrap().starts_with("private,"));
+    }
+}
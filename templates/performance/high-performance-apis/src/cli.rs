@@ -0,0 +1,125 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+
+use crate::config::{parse_override, ConfigSources};
+
+/// Command-line interface for the API server
+#[derive(Debug, Parser)]
+#[command(version, about = "High-performance API server")]
+pub struct Cli {
+    /// Base config file (YAML or TOML); defaults to config/default.*
+    #[arg(short, long, global = true, env = "CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Environment overlay to load from the config directory
+    #[arg(long = "env", global = true, env = "RUST_ENV")]
+    pub environment: Option<String>,
+
+    /// Override a config key, e.g. --set rate_limiting.requests_per_second=500
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE", value_parser = parse_override)]
+    pub overrides: Vec<(String, String)>,
+
+    /// Shorthand for --set server.host=<HOST>
+    #[arg(long, global = true)]
+    pub host: Option<String>,
+
+    /// Shorthand for --set server.port=<PORT>
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    /// Config sources selected on the command line
+    pub fn config_sources(&self) -> ConfigSources {
+        let mut overrides = Vec::new();
+        if let Some(host) = &self.host {
+            overrides.push(("server.host".to_string(), host.clone()));
+        }
+        if let Some(port) = self.port {
+            overrides.push(("server.port".to_string(), port.to_string()));
+        }
+        // Explicit --set flags win over the shorthands
+        overrides.extend(self.overrides.iter().cloned());
+
+        ConfigSources {
+            file: self.config.clone(),
+            environment: self.environment.clone(),
+            overrides,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the API server (default when no subcommand is given)
+    Serve,
+    /// Manage database migrations
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateCommand,
+    },
+    /// Inspect the effective configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Manage the encrypted secrets file
+    Secrets {
+        #[command(subcommand)]
+        action: SecretsCommand,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum SecretsCommand {
+    /// Print a new random key for the encrypted secrets file
+    GenerateKey,
+    /// Encrypt a value read from stdin into the secrets file
+    Set {
+        /// Name to reference as secret://encrypted/<NAME>
+        name: String,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the configuration after all layers are applied
+    Print {
+        /// Mask secrets and passwords embedded in URLs
+        #[arg(long)]
+        redact: bool,
+        #[arg(long, value_enum, default_value_t = ConfigFormat::Yaml)]
+        format: ConfigFormat,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ConfigFormat {
+    Yaml,
+    Json,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum MigrateCommand {
+    /// Show applied, pending and modified migrations
+    Status,
+    /// Apply pending migrations
+    Up {
+        /// Stop after applying this version
+        #[arg(long)]
+        to: Option<String>,
+    },
+    /// Revert the most recently applied migrations
+    Down {
+        #[arg(long, default_value_t = 1)]
+        steps: usize,
+    },
+    /// Revert and re-apply the most recently applied migrations
+    Redo {
+        #[arg(long, default_value_t = 1)]
+        steps: usize,
+    },
+}
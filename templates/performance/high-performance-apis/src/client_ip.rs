@@ -0,0 +1,377 @@
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, Request},
+    http::{header::FORWARDED, request::Parts, HeaderMap, StatusCode},
+    response::Response,
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+
+/// An IPv4 or IPv6 network in CIDR notation. A bare address is a host route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpCidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpCidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+
+        let network: IpAddr = address
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid trusted proxy address '{}'", s))?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| anyhow::anyhow!("Invalid prefix length in trusted proxy '{}'", s))?,
+            None => max_len,
+        };
+
+        Ok(Self { network, prefix_len })
+    }
+}
+
+/// Proxies allowed to report the client address via forwarding headers,
+/// built from `SecurityConfig::trusted_proxies`
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<IpCidr>,
+}
+
+impl TrustedProxies {
+    pub fn from_config(entries: &[String]) -> anyhow::Result<Self> {
+        let networks = entries
+            .iter()
+            .map(|entry| entry.parse())
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { networks })
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+    }
+
+    /// Resolve the originating client address for a connection from `peer`.
+    ///
+    /// Forwarding headers are only believed when the peer is a trusted proxy.
+    /// The hop chain (RFC 7239 `Forwarded`, else `X-Forwarded-For`) is walked
+    /// from the right, skipping trusted proxies; the first untrusted hop is
+    /// the client. Anything to its left was supplied by the client and can be
+    /// spoofed, so it is never used.
+    pub fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+
+        let chain = forwarded_chain(headers).or_else(|| x_forwarded_for_chain(headers));
+        let Some(chain) = chain else {
+            return real_ip(headers).unwrap_or(peer);
+        };
+
+        let mut client = peer;
+        for hop in chain.iter().rev() {
+            match hop {
+                // Obfuscated or garbled hop: nothing further left is reliable,
+                // so stop at the trusted proxy that reported it
+                None => return client,
+                Some(ip) => {
+                    client = *ip;
+                    if !self.is_trusted(*ip) {
+                        return client;
+                    }
+                }
+            }
+        }
+
+        client
+    }
+}
+
+/// `for=` values from every `Forwarded` header, in order
+fn forwarded_chain(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+    let mut chain = Vec::new();
+    let mut present = false;
+
+    for value in headers.get_all(FORWARDED) {
+        present = true;
+        let Ok(value) = value.to_str() else {
+            chain.push(None);
+            continue;
+        };
+
+        for element in value.split(',') {
+            let node = element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim().eq_ignore_ascii_case("for").then(|| value.trim())
+            });
+
+            // Elements without `for=` describe other hops' details only
+            if let Some(node) = node {
+                chain.push(parse_node(node));
+            }
+        }
+    }
+
+    present.then_some(chain)
+}
+
+/// Entries from every `X-Forwarded-For` header, in order
+fn x_forwarded_for_chain(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+    let mut chain = Vec::new();
+    let mut present = false;
+
+    for value in headers.get_all("x-forwarded-for") {
+        present = true;
+        match value.to_str() {
+            Ok(value) => chain.extend(value.split(',').map(parse_node)),
+            Err(_) => chain.push(None),
+        }
+    }
+
+    present.then_some(chain)
+}
+
+fn real_ip(headers: &HeaderMap) -> Option<IpAddr> {
+    headers
+        .get("x-real-ip")
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_node)
+}
+
+/// Parse a hop: `1.2.3.4`, `1.2.3.4:80`, `"[2001:db8::1]:4711"` or `2001:db8::1`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+
+    node.strip_prefix('[')
+        .and_then(|rest| rest.split_once(']'))
+        .and_then(|(ip, _)| ip.parse().ok())
+}
+
+/// The resolved originating client address, available as an extractor and
+/// as a request extension once [`ClientIpLayer`] has run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(client_ip) = parts.extensions.get::<ClientIp>() {
+            return Ok(*client_ip);
+        }
+
+        // Without the layer, only the socket peer can be trusted
+        parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|connect_info| ClientIp(connect_info.0.ip()))
+            .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Client address unavailable"))
+    }
+}
+
+/// Resolves [`ClientIp`] once per request so later middleware and handlers
+/// share the same answer
+#[derive(Clone)]
+pub struct ClientIpLayer {
+    trusted_proxies: Arc<TrustedProxies>,
+}
+
+impl ClientIpLayer {
+    pub fn new(trusted_proxies: TrustedProxies) -> Self {
+        Self {
+            trusted_proxies: Arc::new(trusted_proxies),
+        }
+    }
+}
+
+impl<S> Layer<S> for ClientIpLayer {
+    type Service = ClientIpService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ClientIpService {
+            inner,
+            trusted_proxies: self.trusted_proxies.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ClientIpService<S> {
+    inner: S,
+    trusted_proxies: Arc<TrustedProxies>,
+}
+
+impl<S> Service<Request> for ClientIpService<S>
+where
+    S: Service<Request, Response = Response>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|connect_info| connect_info.0.ip());
+
+        if let Some(peer) = peer {
+            let client_ip = self.trusted_proxies.resolve(peer, request.headers());
+            request.extensions_mut().insert(ClientIp(client_ip));
+        }
+
+        self.inner.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxies(entries: &[&str]) -> TrustedProxies {
+        TrustedProxies::from_config(&entries.iter().map(|e| e.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr_matching() {
+        let cidr: IpCidr = "10.0.0.0/8".parse().unwrap();
+        assert!(cidr.contains(ip("10.1.2.3")));
+        assert!(!cidr.contains(ip("11.0.0.1")));
+        assert!(cidr.contains(ip("::ffff:10.1.2.3")));
+
+        let cidr: IpCidr = "2001:db8::/32".parse().unwrap();
+        assert!(cidr.contains(ip("2001:db8:cafe::17")));
+        assert!(!cidr.contains(ip("2001:db9::1")));
+
+        let host: IpCidr = "192.0.2.1".parse().unwrap();
+        assert!(host.contains(ip("192.0.2.1")));
+        assert!(!host.contains(ip("192.0.2.2")));
+
+        assert!("0.0.0.0/0".parse::<IpCidr>().unwrap().contains(ip("8.8.8.8")));
+        assert!("10.0.0.0/33".parse::<IpCidr>().is_err());
+        assert!("proxy.local".parse::<IpCidr>().is_err());
+    }
+
+    #[test]
+    fn test_untrusted_peer_headers_are_ignored() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "1.1.1.1".parse().unwrap());
+        headers.insert("x-real-ip", "1.1.1.1".parse().unwrap());
+
+        let resolved = proxies(&["10.0.0.0/8"]).resolve(ip("203.0.113.9"), &headers);
+        assert_eq!(resolved, ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn test_x_forwarded_for_walks_from_the_right() {
+        let mut headers = HeaderMap::new();
+        // Client spoofs 1.1.1.1; the edge proxy appends the real address
+        headers.insert("x-forwarded-for", "1.1.1.1, 198.51.100.7, 10.0.0.2".parse().unwrap());
+
+        let resolved = proxies(&["10.0.0.0/8"]).resolve(ip("10.0.0.1"), &headers);
+        assert_eq!(resolved, ip("198.51.100.7"));
+    }
+
+    #[test]
+    fn test_multiple_x_forwarded_for_headers_are_concatenated() {
+        let mut headers = HeaderMap::new();
+        headers.append("x-forwarded-for", "1.1.1.1".parse().unwrap());
+        headers.append("x-forwarded-for", "198.51.100.7:5555".parse().unwrap());
+
+        let resolved = proxies(&["10.0.0.0/8"]).resolve(ip("10.0.0.1"), &headers);
+        assert_eq!(resolved, ip("198.51.100.7"));
+    }
+
+    #[test]
+    fn test_forwarded_header_takes_precedence() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            FORWARDED,
+            r#"for=1.1.1.1, for="[2001:db8:cafe::17]:4711";proto=https, For=10.0.0.3;by=10.0.0.1"#
+                .parse()
+                .unwrap(),
+        );
+        headers.insert("x-forwarded-for", "9.9.9.9".parse().unwrap());
+
+        let resolved = proxies(&["10.0.0.0/8"]).resolve(ip("10.0.0.1"), &headers);
+        assert_eq!(resolved, ip("2001:db8:cafe::17"));
+    }
+
+    #[test]
+    fn test_obfuscated_hop_stops_the_walk() {
+        let mut headers = HeaderMap::new();
+        headers.insert(FORWARDED, "for=1.1.1.1, for=_hidden, for=10.0.0.3".parse().unwrap());
+
+        let resolved = proxies(&["10.0.0.0/8"]).resolve(ip("10.0.0.1"), &headers);
+        assert_eq!(resolved, ip("10.0.0.3"));
+    }
+
+    #[test]
+    fn test_all_trusted_chain_returns_leftmost_hop() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "10.0.0.5, 10.0.0.4".parse().unwrap());
+
+        let resolved = proxies(&["10.0.0.0/8"]).resolve(ip("10.0.0.1"), &headers);
+        assert_eq!(resolved, ip("10.0.0.5"));
+    }
+
+    #[test]
+    fn test_real_ip_only_without_forwarding_chain() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-real-ip", "198.51.100.7".parse().unwrap());
+
+        let resolved = proxies(&["10.0.0.0/8"]).resolve(ip("10.0.0.1"), &headers);
+        assert_eq!(resolved, ip("198.51.100.7"));
+    }
+}
@@ -0,0 +1,134 @@
+use notify::{RecursiveMode, Watcher};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, error, info, warn};
+
+use super::{Config, ConfigSources};
+
+/// Reloads configuration from its sources and publishes every validated
+/// change through a watch channel.
+///
+/// Only the sections covered by [`Config::with_reloadable`] change at
+/// runtime; edits to anything else are reported and take effect on restart.
+pub struct ConfigReloader {
+    sources: ConfigSources,
+    updates: watch::Sender<Arc<Config>>,
+}
+
+impl ConfigReloader {
+    pub fn new(sources: ConfigSources, initial: Config) -> Self {
+        let (updates, _) = watch::channel(Arc::new(initial));
+        Self { sources, updates }
+    }
+
+    /// Receiver that always holds the current configuration
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Config>> {
+        self.updates.subscribe()
+    }
+
+    pub fn current(&self) -> Arc<Config> {
+        self.updates.borrow().clone()
+    }
+
+    /// Reload from the sources, returning whether anything changed. An
+    /// invalid configuration is rejected as a whole and the current one kept.
+    pub fn reload(&self) -> anyhow::Result<bool> {
+        let candidate = Config::load(&self.sources)?;
+        let current = self.current();
+        let next = current.with_reloadable(&candidate);
+        next.validate()?;
+
+        let restart_only = changed_sections(&next, &candidate)?;
+        if !restart_only.is_empty() {
+            warn!(
+                "Configuration changes to {} require a restart and were not applied",
+                restart_only.join(", ")
+            );
+        }
+
+        if serde_json::to_value(&next)? == serde_json::to_value(current.as_ref())? {
+            return Ok(false);
+        }
+
+        self.updates.send_replace(Arc::new(next));
+        Ok(true)
+    }
+
+    /// Reload whenever a file in the config directory changes or the process
+    /// receives SIGHUP
+    pub async fn run(self) -> anyhow::Result<()> {
+        let (file_events, mut file_changes) = mpsc::channel(1);
+        let config_dir = self.sources.config_dir();
+
+        // Watch the directory rather than the files, so editors and
+        // Kubernetes ConfigMaps that replace files atomically are picked up
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if event.is_ok_and(|event| !event.kind.is_access()) {
+                // A pending notification already covers this change
+                let _ = file_events.try_send(());
+            }
+        })?;
+        match watcher.watch(&config_dir, RecursiveMode::NonRecursive) {
+            Ok(()) => info!("Watching {} for configuration changes", config_dir.display()),
+            Err(e) => warn!("Not watching {} for configuration changes: {}", config_dir.display(), e),
+        }
+
+        #[cfg(unix)]
+        let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+
+        loop {
+            #[cfg(unix)]
+            let sighup = hangup.recv();
+            #[cfg(not(unix))]
+            let sighup = std::future::pending::<Option<()>>();
+
+            let trigger = tokio::select! {
+                Some(()) = file_changes.recv() => {
+                    // Let writes settle so a half-written file isn't loaded
+                    tokio::time::sleep(Duration::from_millis(250)).await;
+                    while file_changes.try_recv().is_ok() {}
+                    "file change"
+                }
+                Some(()) = sighup => "SIGHUP",
+                else => return Ok(()),
+            };
+
+            match self.reload() {
+                Ok(true) => info!("Configuration reloaded after {}", trigger),
+                Ok(false) => debug!("Configuration unchanged after {}", trigger),
+                Err(e) => error!("Rejected configuration reload after {}: {:#}", trigger, e),
+            }
+        }
+    }
+}
+
+/// Top-level sections that differ between two configurations
+pub(crate) fn changed_sections(a: &Config, b: &Config) -> anyhow::Result<Vec<String>> {
+    let (serde_json::Value::Object(a), serde_json::Value::Object(b)) =
+        (serde_json::to_value(a)?, serde_json::to_value(b)?)
+    else {
+        anyhow::bail!("configuration did not serialize to a map");
+    };
+
+    Ok(a.iter()
+        .filter(|(section, value)| b.get(*section) != Some(*value))
+        .map(|(section, _)| section.clone())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_sections() {
+        let current = Config::default();
+        let mut candidate = Config::default();
+        candidate.server.port = 4000;
+        candidate.rate_limiting.burst_size = 1;
+
+        let next = current.with_reloadable(&candidate);
+        assert_eq!(changed_sections(&next, &candidate).unwrap(), vec!["server".to_string()]);
+        assert!(changed_sections(&candidate, &candidate).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,57 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use tracing::error;
+
+use crate::{
+    auth::{scopes::ScopeError, AuthError},
+    problem::ProblemDetails,
+};
+
+/// Error returned by handlers, rendered as an `application/problem+json`
+/// response
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+    #[error(transparent)]
+    Scope(#[from] ScopeError),
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::NotFound(detail) => ProblemDetails::new(StatusCode::NOT_FOUND)
+                .with_detail(detail)
+                .into_response(),
+            Self::BadRequest(detail) => ProblemDetails::new(StatusCode::BAD_REQUEST)
+                .with_detail(detail)
+                .into_response(),
+            Self::Conflict(detail) => ProblemDetails::new(StatusCode::CONFLICT)
+                .with_detail(detail)
+                .into_response(),
+            Self::Auth(e) => e.into_response(),
+            Self::Scope(e) => e.into_response(),
+            // Internal details are logged, never returned to the client
+            Self::Database(e) => {
+                error!("Database error: {}", e);
+                ProblemDetails::new(StatusCode::INTERNAL_SERVER_ERROR).into_response()
+            }
+            Self::Internal(e) => {
+                error!("Internal error: {:#}", e);
+                ProblemDetails::new(StatusCode::INTERNAL_SERVER_ERROR).into_response()
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use async_graphql::{EmptySubscription, MergedObject};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use axum::{
+    extract::State,
+    http::HeaderMap,
+    response::{Html, IntoResponse},
+    routing::get,
+    Extension, Router,
+};
+
+use crate::{
+    audit::{actions, AuditEvent, AuditLog, AuditSource},
+    auth::{AuthError, Authenticator},
+    cache::ResponseCache,
+    performance::PerformanceAnalytics,
+    server::tls::ClientCertificate,
+    AppState,
+};
+
+pub mod api_keys;
+pub mod guards;
+pub mod performance;
+
+use self::{
+    api_keys::{ApiKeyMutation, ApiKeyQuery},
+    performance::PerformanceQuery,
+};
+
+#[derive(MergedObject, Default)]
+pub struct Query(ApiKeyQuery, PerformanceQuery);
+
+#[derive(MergedObject, Default)]
+pub struct Mutation(ApiKeyMutation);
+
+pub type Schema = async_graphql::Schema<Query, Mutation, EmptySubscription>;
+
+pub async fn create_schema(
+    auth: Authenticator,
+    audit: AuditLog,
+    performance: PerformanceAnalytics,
+    cache: ResponseCache,
+) -> anyhow::Result<Schema> {
+    Ok(Schema::build(Query::default(), Mutation::default(), EmptySubscription)
+        .data(auth.api_keys().clone())
+        .data(audit)
+        .data(performance)
+        .data(cache)
+        .finish())
+}
+
+pub fn create_routes(schema: Schema) -> Router<AppState> {
+    Router::new()
+        .route("/graphql", get(graphiql).post(graphql_handler))
+        .layer(Extension(schema))
+}
+
+/// Runs a GraphQL request with the caller's [`Principal`](crate::auth::Principal)
+/// in the context. Requests without credentials still run, so resolvers that
+/// need a caller must ask for one; invalid credentials are rejected outright.
+async fn graphql_handler(
+    State(state): State<AppState>,
+    Extension(schema): Extension<Schema>,
+    headers: HeaderMap,
+    certificate: Option<Extension<ClientCertificate>>,
+    source: AuditSource,
+    request: GraphQLRequest,
+) -> Result<GraphQLResponse, AuthError> {
+    let mut request = request.into_inner();
+    let certificate = certificate.as_ref().map(|Extension(certificate)| certificate);
+    match state.auth.authenticate(&headers, certificate, source.ip_address).await {
+        Ok(principal) => request = request.data(principal),
+        Err(AuthError::MissingToken) => {}
+        Err(e) => {
+            state.audit.record(
+                AuditEvent::new(actions::AUTH_FAILURE)
+                    .detail("reason", e.to_string())
+                    .detail("path", "/graphql")
+                    .source(&source),
+            );
+            return Err(e);
+        }
+    }
+
+    Ok(schema.execute(request.data(source)).await.into())
+}
+
+async fn graphiql() -> impl IntoResponse {
+    Html(async_graphql::http::GraphiQLSource::build().endpoint("/graphql").finish())
+}
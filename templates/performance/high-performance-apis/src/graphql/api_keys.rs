@@ -0,0 +1,129 @@
+use async_graphql::{Context, Error, Object, Result};
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::guards::ScopeGuard;
+use crate::{
+    audit::{actions, AuditEvent, AuditLog, AuditSource},
+    auth::{
+        api_keys::{self, check_grantable, ApiKey, ApiKeyStore, IssuedApiKey, NewApiKey},
+        Principal,
+    },
+    cache::ResponseCache,
+};
+
+/// The calling user. GraphQL manages the caller's own keys; admins manage
+/// anyone's through `/admin/api-keys`.
+fn caller(ctx: &Context<'_>) -> Result<Uuid> {
+    ctx.data_opt::<Principal>()
+        .ok_or_else(|| Error::new("Authentication required"))?
+        .user_id()
+        .ok_or_else(|| Error::new("API keys can only be managed by a user"))
+}
+
+/// Fail unless `id` is one of the caller's keys
+async fn ensure_owned(ctx: &Context<'_>, id: Uuid) -> Result<()> {
+    let user_id = caller(ctx)?;
+    match ctx.data::<ApiKeyStore>()?.get(id).await? {
+        Some(key) if key.user_id == user_id => Ok(()),
+        _ => Err(Error::new(format!("No API key {}", id))),
+    }
+}
+
+/// Record a key change made by the caller
+fn audit(ctx: &Context<'_>, event: AuditEvent) {
+    let (Ok(audit), Some(principal)) = (ctx.data::<AuditLog>(), ctx.data_opt::<Principal>()) else {
+        return;
+    };
+    let source = ctx.data_opt::<AuditSource>().cloned().unwrap_or_default();
+    audit.record(event.actor(principal).source(&source));
+}
+
+/// Drop cached `/admin/api-keys` listings after a key changes
+async fn invalidate_cached(ctx: &Context<'_>) {
+    if let Ok(cache) = ctx.data::<ResponseCache>() {
+        cache.invalidate(&[api_keys::CACHE_TAG]).await;
+    }
+}
+
+#[derive(Default)]
+pub struct ApiKeyQuery;
+
+#[Object]
+impl ApiKeyQuery {
+    /// The caller's API keys, newest first
+    #[graphql(guard = "ScopeGuard::new(\"api_keys:read\")")]
+    async fn api_keys(&self, ctx: &Context<'_>, #[graphql(default)] include_revoked: bool) -> Result<Vec<ApiKey>> {
+        let user_id = caller(ctx)?;
+        Ok(ctx.data::<ApiKeyStore>()?.list(Some(user_id), include_revoked).await?)
+    }
+}
+
+#[derive(Default)]
+pub struct ApiKeyMutation;
+
+#[Object]
+impl ApiKeyMutation {
+    /// Create a key for the caller. The returned `key` is shown only once.
+    #[graphql(guard = "ScopeGuard::new(\"api_keys:write\")")]
+    async fn create_api_key(&self, ctx: &Context<'_>, input: NewApiKey) -> Result<IssuedApiKey> {
+        let user_id = caller(ctx)?;
+        if input.user_id.is_some_and(|requested| requested != user_id) {
+            return Err(Error::new("Keys for other users are created through /admin/api-keys"));
+        }
+        if input.name.trim().is_empty() {
+            return Err(Error::new("name must not be empty"));
+        }
+        if let Some(principal) = ctx.data_opt::<Principal>() {
+            check_grantable(principal, &input).map_err(|e| Error::new(e.to_string()))?;
+        }
+        let store = ctx.data::<ApiKeyStore>()?;
+        if let Some(plan) = &input.plan {
+            if !store.plan_exists(plan).await? {
+                return Err(Error::new(format!("Unknown plan '{}'", plan)));
+            }
+        }
+
+        let created = store.create(user_id, input).await?;
+        invalidate_cached(ctx).await;
+        audit(
+            ctx,
+            AuditEvent::new(actions::API_KEY_CREATED)
+                .resource("api_key", created.api_key.id)
+                .detail("owner", user_id.to_string())
+                .detail("name", created.api_key.name.as_str()),
+        );
+        Ok(created)
+    }
+
+    /// Replace a key, keeping the old one valid for `grace_seconds`
+    #[graphql(guard = "ScopeGuard::new(\"api_keys:write\")")]
+    async fn rotate_api_key(&self, ctx: &Context<'_>, id: Uuid, grace_seconds: Option<u64>) -> Result<IssuedApiKey> {
+        ensure_owned(ctx, id).await?;
+        let rotated = ctx
+            .data::<ApiKeyStore>()?
+            .rotate(id, grace_seconds.map(Duration::from_secs))
+            .await?
+            .ok_or_else(|| Error::new(format!("API key {} is already revoked or rotated", id)))?;
+        invalidate_cached(ctx).await;
+        audit(
+            ctx,
+            AuditEvent::new(actions::API_KEY_ROTATED)
+                .resource("api_key", id)
+                .detail("replacement", rotated.api_key.id.to_string()),
+        );
+        Ok(rotated)
+    }
+
+    /// Revoke a key immediately, returning whether it was active
+    #[graphql(guard = "ScopeGuard::new(\"api_keys:write\")")]
+    async fn revoke_api_key(&self, ctx: &Context<'_>, id: Uuid) -> Result<bool> {
+        ensure_owned(ctx, id).await?;
+        let revoked = ctx.data::<ApiKeyStore>()?.revoke(id).await?;
+        if revoked {
+            invalidate_cached(ctx).await;
+            audit(ctx, AuditEvent::new(actions::API_KEY_REVOKED).resource("api_key", id));
+        }
+        Ok(revoked)
+    }
+}
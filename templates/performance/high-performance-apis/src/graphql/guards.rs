@@ -0,0 +1,34 @@
+use async_graphql::{Context, Error, ErrorExtensions, Guard, Result};
+
+use crate::auth::{scopes::check_scopes, Principal};
+
+/// Field guard requiring a scope, the GraphQL counterpart of
+/// `RequireScopeLayer`:
+///
+/// ```ignore
+/// #[graphql(guard = "ScopeGuard::new(\"api_keys:read\")")]
+/// ```
+pub struct ScopeGuard {
+    scope: &'static str,
+}
+
+impl ScopeGuard {
+    pub fn new(scope: &'static str) -> Self {
+        Self { scope }
+    }
+}
+
+impl Guard for ScopeGuard {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        let principal = ctx
+            .data_opt::<Principal>()
+            .ok_or_else(|| Error::new("Authentication required").extend_with(|_, e| e.set("code", "UNAUTHENTICATED")))?;
+
+        check_scopes(principal, &[self.scope]).map_err(|error| {
+            Error::new(error.to_string()).extend_with(|_, e| {
+                e.set("code", "FORBIDDEN");
+                e.set("requiredScopes", error.missing.clone());
+            })
+        })
+    }
+}
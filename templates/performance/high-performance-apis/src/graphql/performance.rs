@@ -0,0 +1,37 @@
+use async_graphql::{Context, Object, Result};
+
+use super::guards::ScopeGuard;
+use crate::performance::{EndpointPerformance, PerformanceAnalytics, PerformanceFilter, PerformancePoint};
+
+#[derive(Default)]
+pub struct PerformanceQuery;
+
+#[Object]
+impl PerformanceQuery {
+    /// The p99 latency target, in milliseconds, that `exceedsSlo` is
+    /// measured against
+    #[graphql(guard = "ScopeGuard::new(\"metrics:read\")")]
+    async fn performance_slo_ms(&self, ctx: &Context<'_>) -> Result<f64> {
+        Ok(ctx.data::<PerformanceAnalytics>()?.slo_ms())
+    }
+
+    /// Per-endpoint totals over the range, slowest endpoints first
+    #[graphql(guard = "ScopeGuard::new(\"metrics:read\")")]
+    async fn endpoint_performance(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] filter: PerformanceFilter,
+    ) -> Result<Vec<EndpointPerformance>> {
+        Ok(ctx.data::<PerformanceAnalytics>()?.endpoints(&filter).await?)
+    }
+
+    /// Hourly figures per endpoint, oldest first
+    #[graphql(guard = "ScopeGuard::new(\"metrics:read\")")]
+    async fn performance_series(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] filter: PerformanceFilter,
+    ) -> Result<Vec<PerformancePoint>> {
+        Ok(ctx.data::<PerformanceAnalytics>()?.series(&filter).await?)
+    }
+}
@@ -0,0 +1,452 @@
+use anyhow::Context;
+use axum::{
+    async_trait,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json},
+    routing::{get, post},
+    Router,
+};
+use chrono::{DateTime, DurationRound, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, PgConnection};
+use std::{sync::Arc, time::Duration};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    config::JobsConfig, database::DatabasePool, error::AppError, metrics::Metrics,
+    middleware::scope::RequireScopeLayer, shutdown::Shutdown, AppState,
+};
+
+pub mod builtin;
+
+/// First key of the two-part advisory lock taken while a job runs, so job
+/// locks can't collide with advisory locks taken for other reasons
+const JOB_LOCK_NAMESPACE: i32 = 0x6a6f6273; // "jobs"
+
+/// Schedule value that turns a job off
+pub const SCHEDULE_OFF: &str = "off";
+
+/// When a job runs: a cron expression in UTC (`*/15 * * * *`, `@daily`) or a
+/// fixed interval (`@every 5m`). Intervals are aligned to the Unix epoch so
+/// every replica computes the same run times.
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Every(Duration),
+    Cron(Box<cron::Schedule>),
+}
+
+impl std::str::FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(every) = s.strip_prefix("@every") {
+            let interval = humantime_serde::re::humantime::parse_duration(every.trim())
+                .with_context(|| format!("Invalid interval in schedule '{}'", s))?;
+            if interval < Duration::from_secs(1) {
+                anyhow::bail!("Schedule '{}' must be at least one second apart", s);
+            }
+            return Ok(Self::Every(interval));
+        }
+
+        // The cron crate wants a leading seconds field; accept the usual five
+        let expression = match s.split_whitespace().count() {
+            5 => format!("0 {}", s),
+            _ => s.to_string(),
+        };
+        let schedule = expression
+            .parse::<cron::Schedule>()
+            .with_context(|| format!("Invalid schedule '{}', expected a cron expression or @every <duration>", s))?;
+        Ok(Self::Cron(Box::new(schedule)))
+    }
+}
+
+impl Schedule {
+    /// Parse a configured schedule, returning `None` when it is `off`
+    pub fn from_config(s: &str) -> anyhow::Result<Option<Self>> {
+        if s.trim() == SCHEDULE_OFF {
+            return Ok(None);
+        }
+        s.parse().map(Some)
+    }
+
+    /// The first run time strictly after `after`
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Every(interval) => {
+                let interval = chrono::Duration::from_std(*interval).ok()?;
+                let slot = after.duration_trunc(interval).ok()?;
+                Some(slot + interval)
+            }
+            Self::Cron(schedule) => schedule.after(&after).next(),
+        }
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Every(interval) => write!(f, "@every {}", humantime_serde::re::humantime::format_duration(*interval)),
+            Self::Cron(schedule) => write!(f, "{}", schedule),
+        }
+    }
+}
+
+/// A maintenance task the [`Scheduler`] can run
+#[async_trait]
+pub trait Job: Send + Sync {
+    /// Unique name, used in config, the run history and `/admin/jobs`
+    fn name(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    /// Run once, returning a short summary for the run history
+    async fn run(&self) -> anyhow::Result<String>;
+}
+
+/// What started a run, recorded in `job_runs.triggered_by`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Trigger {
+    Schedule,
+    Manual,
+}
+
+impl Trigger {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Schedule => "schedule",
+            Self::Manual => "manual",
+        }
+    }
+}
+
+/// One row of `job_runs`
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct JobRun {
+    pub id: Uuid,
+    pub job: String,
+    pub triggered_by: String,
+    pub scheduled_for: Option<DateTime<Utc>>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// `running`, `succeeded`, `failed` or `abandoned`
+    pub status: String,
+    pub output: Option<String>,
+    pub instance: Option<String>,
+}
+
+const JOB_RUN_COLUMNS: &str =
+    "id, job, triggered_by, scheduled_for, started_at, finished_at, status, output, instance";
+
+/// A run in progress. The advisory lock lives on its own connection, so it
+/// is released when the run finishes or, if the process dies, when Postgres
+/// notices the connection is gone.
+struct RunGuard {
+    lock: PgConnection,
+    run: JobRun,
+}
+
+struct ScheduledJob {
+    job: Arc<dyn Job>,
+    schedule: Option<Schedule>,
+}
+
+/// Runs maintenance jobs on their schedules. Every replica runs the
+/// scheduler; a Postgres advisory lock keeps a job from running twice at
+/// once, and the run history records each scheduled run time only once, so a
+/// run is skipped by replicas that get there second.
+pub struct Scheduler {
+    db: DatabasePool,
+    instance: String,
+    history_retention: Duration,
+    jobs: Vec<ScheduledJob>,
+    metrics: Metrics,
+    // Manually triggered runs are stopped with the scheduled ones
+    shutdown: Shutdown,
+}
+
+impl Scheduler {
+    pub fn new(db: DatabasePool, config: &JobsConfig, metrics: Metrics, shutdown: Shutdown) -> Self {
+        let instance = std::env::var("HOSTNAME").unwrap_or_else(|_| Uuid::new_v4().to_string());
+        Self {
+            db,
+            instance,
+            history_retention: config.history_retention,
+            jobs: Vec::new(),
+            metrics,
+            shutdown,
+        }
+    }
+
+    /// Add a job, to run on `schedule` or only when triggered if `None`
+    pub fn register(&mut self, job: impl Job + 'static, schedule: Option<Schedule>) {
+        self.jobs.push(ScheduledJob {
+            job: Arc::new(job),
+            schedule,
+        });
+    }
+
+    /// Spawn a task per scheduled job, stopped on shutdown. A run in
+    /// progress is left to finish or is marked abandoned on the next start.
+    pub fn start(self: &Arc<Self>, shutdown: &Shutdown) {
+        for (index, scheduled) in self.jobs.iter().enumerate() {
+            if let Some(schedule) = &scheduled.schedule {
+                info!("Scheduled job {} ({})", scheduled.job.name(), schedule);
+                shutdown.spawn(scheduled.job.name(), self.clone().run_schedule(index));
+            }
+        }
+    }
+
+    async fn run_schedule(self: Arc<Self>, index: usize) {
+        let scheduled = &self.jobs[index];
+        let Some(schedule) = &scheduled.schedule else {
+            return;
+        };
+
+        loop {
+            let now = Utc::now();
+            let Some(next) = schedule.next_after(now) else {
+                warn!("Job {} has no upcoming runs", scheduled.job.name());
+                return;
+            };
+            tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+
+            match self.begin(scheduled.job.name(), Trigger::Schedule, Some(next)).await {
+                Ok(Some(guard)) => self.execute(scheduled.job.as_ref(), guard).await,
+                Ok(None) => debug!("Job {} for {} ran elsewhere, skipping", scheduled.job.name(), next),
+                Err(e) => warn!("Failed to start job {}: {:#}", scheduled.job.name(), e),
+            }
+        }
+    }
+
+    /// Take the job's lock and record the run. Returns `None` if the job is
+    /// already running, or this scheduled run already happened elsewhere.
+    async fn begin(
+        &self,
+        name: &'static str,
+        trigger: Trigger,
+        scheduled_for: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Option<RunGuard>> {
+        let mut lock = PgConnection::connect_with(&self.db.connect_options()).await?;
+        let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock($1, hashtext($2))")
+            .bind(JOB_LOCK_NAMESPACE)
+            .bind(name)
+            .fetch_one(&mut lock)
+            .await?;
+        if !locked {
+            let _ = lock.close().await;
+            return Ok(None);
+        }
+
+        // Holding the lock means no earlier run is still going
+        sqlx::query("UPDATE job_runs SET status = 'abandoned', finished_at = NOW() WHERE job = $1 AND status = 'running'")
+            .bind(name)
+            .execute(&self.db)
+            .await?;
+
+        let run = sqlx::query_as::<_, JobRun>(&format!(
+            "INSERT INTO job_runs (job, triggered_by, scheduled_for, instance)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (job, scheduled_for) DO NOTHING
+             RETURNING {}",
+            JOB_RUN_COLUMNS
+        ))
+        .bind(name)
+        .bind(trigger.as_str())
+        .bind(scheduled_for)
+        .bind(&self.instance)
+        .fetch_optional(&self.db)
+        .await?;
+
+        match run {
+            Some(run) => Ok(Some(RunGuard { lock, run })),
+            None => {
+                let _ = lock.close().await;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn execute(&self, job: &dyn Job, guard: RunGuard) {
+        let started = std::time::Instant::now();
+        let result = job.run().await;
+        self.metrics.record_job_run(job.name(), started.elapsed(), result.is_ok());
+
+        let (status, output) = match &result {
+            Ok(summary) => {
+                info!("Job {} finished: {}", job.name(), summary);
+                ("succeeded", summary.clone())
+            }
+            Err(e) => {
+                warn!("Job {} failed: {:#}", job.name(), e);
+                ("failed", format!("{:#}", e))
+            }
+        };
+
+        let recorded = sqlx::query("UPDATE job_runs SET status = $2, output = $3, finished_at = NOW() WHERE id = $1")
+            .bind(guard.run.id)
+            .bind(status)
+            .bind(output)
+            .execute(&self.db)
+            .await;
+        if let Err(e) = recorded {
+            warn!("Failed to record run of job {}: {}", job.name(), e);
+        }
+        let _ = guard.lock.close().await;
+
+        let pruned = sqlx::query("DELETE FROM job_runs WHERE job = $1 AND started_at < NOW() - $2::interval")
+            .bind(job.name())
+            .bind(format!("{} seconds", self.history_retention.as_secs()))
+            .execute(&self.db)
+            .await;
+        if let Err(e) = pruned {
+            warn!("Failed to prune run history of job {}: {}", job.name(), e);
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<&ScheduledJob> {
+        self.jobs.iter().find(|scheduled| scheduled.job.name() == name)
+    }
+
+    /// Start a run of `name` now, returning `None` if it is already running.
+    /// Like a scheduled run, it is cancelled on shutdown.
+    pub async fn trigger(self: &Arc<Self>, name: &str) -> anyhow::Result<Option<JobRun>> {
+        let Some(scheduled) = self.find(name) else {
+            anyhow::bail!("No job named {}", name);
+        };
+        let Some(guard) = self.begin(scheduled.job.name(), Trigger::Manual, None).await? else {
+            return Ok(None);
+        };
+
+        let run = guard.run.clone();
+        let scheduler = self.clone();
+        let job = scheduled.job.clone();
+        self.shutdown
+            .spawn(scheduled.job.name(), async move { scheduler.execute(job.as_ref(), guard).await });
+        Ok(Some(run))
+    }
+
+    pub async fn runs(&self, name: &str, limit: i64) -> anyhow::Result<Vec<JobRun>> {
+        Ok(sqlx::query_as::<_, JobRun>(&format!(
+            "SELECT {} FROM job_runs WHERE job = $1 ORDER BY started_at DESC LIMIT $2",
+            JOB_RUN_COLUMNS
+        ))
+        .bind(name)
+        .bind(limit)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    /// Every job with its schedule, next run and latest run
+    pub async fn status(&self) -> anyhow::Result<Vec<JobStatus>> {
+        let latest = sqlx::query_as::<_, JobRun>(&format!(
+            "SELECT DISTINCT ON (job) {} FROM job_runs ORDER BY job, started_at DESC",
+            JOB_RUN_COLUMNS
+        ))
+        .fetch_all(&self.db)
+        .await?;
+
+        let now = Utc::now();
+        Ok(self
+            .jobs
+            .iter()
+            .map(|scheduled| JobStatus {
+                name: scheduled.job.name(),
+                description: scheduled.job.description(),
+                schedule: scheduled.schedule.as_ref().map(ToString::to_string),
+                next_run: scheduled.schedule.as_ref().and_then(|schedule| schedule.next_after(now)),
+                last_run: latest.iter().find(|run| run.job == scheduled.job.name()).cloned(),
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobStatus {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// `None` for jobs that only run when triggered
+    pub schedule: Option<String>,
+    pub next_run: Option<DateTime<Utc>>,
+    pub last_run: Option<JobRun>,
+}
+
+/// `/admin/jobs` routes, reading with `admin:read` and triggering runs with
+/// `admin:write`
+pub fn routes() -> Router<AppState> {
+    let read = RequireScopeLayer::new(["admin:read"]);
+    let write = RequireScopeLayer::new(["admin:write"]);
+
+    Router::new()
+        .route("/", get(list_jobs).layer(read.clone()))
+        .route("/:name/runs", get(list_runs).layer(read))
+        .route("/:name/run", post(trigger_job).layer(write))
+}
+
+async fn list_jobs(State(state): State<AppState>) -> Result<Json<Vec<JobStatus>>, AppError> {
+    Ok(Json(state.jobs.status().await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct RunsParams {
+    limit: Option<i64>,
+}
+
+async fn list_runs(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(params): Query<RunsParams>,
+) -> Result<Json<Vec<JobRun>>, AppError> {
+    if state.jobs.find(&name).is_none() {
+        return Err(AppError::NotFound(format!("No job named {}", name)));
+    }
+    let limit = params.limit.unwrap_or(20).clamp(1, 500);
+    Ok(Json(state.jobs.runs(&name, limit).await?))
+}
+
+async fn trigger_job(State(state): State<AppState>, Path(name): Path<String>) -> Result<impl IntoResponse, AppError> {
+    if state.jobs.find(&name).is_none() {
+        return Err(AppError::NotFound(format!("No job named {}", name)));
+    }
+    match state.jobs.trigger(&name).await? {
+        Some(run) => Ok((StatusCode::ACCEPTED, Json(run))),
+        None => Err(AppError::Conflict(format!("Job {} is already running", name))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        Utc.from_utc_datetime(&s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_schedules_parse() {
+        assert!(matches!("@every 5m".parse::<Schedule>().unwrap(), Schedule::Every(d) if d == Duration::from_secs(300)));
+        assert!(matches!("*/15 * * * *".parse::<Schedule>().unwrap(), Schedule::Cron(_)));
+        assert!(matches!("@daily".parse::<Schedule>().unwrap(), Schedule::Cron(_)));
+        assert!(Schedule::from_config(SCHEDULE_OFF).unwrap().is_none());
+
+        assert!("@every soon".parse::<Schedule>().is_err());
+        assert!("@every 10ms".parse::<Schedule>().is_err());
+        assert!("61 * * * *".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn test_next_run_is_aligned() {
+        let now = at("2024-06-01T10:07:30");
+
+        let every = "@every 5m".parse::<Schedule>().unwrap();
+        assert_eq!(every.next_after(now), Some(at("2024-06-01T10:10:00")));
+        // A run time is never repeated, even when asked exactly at it
+        assert_eq!(every.next_after(at("2024-06-01T10:10:00")), Some(at("2024-06-01T10:15:00")));
+
+        let cron = "0 3 * * *".parse::<Schedule>().unwrap();
+        assert_eq!(cron.next_after(now), Some(at("2024-06-02T03:00:00")));
+    }
+}
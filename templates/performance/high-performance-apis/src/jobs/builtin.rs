@@ -0,0 +1,114 @@
+use axum::async_trait;
+
+use super::Job;
+use crate::{
+    cache::ResponseCache,
+    database::{self, DatabasePool},
+    metrics::partitions::PartitionManager,
+    performance,
+    rate_limiting::RateLimiter,
+};
+
+/// Drops expired request metric partitions (creating upcoming ones while at
+/// it) and deletes old rows from the unpartitioned `performance_metrics`
+pub struct MetricRetention {
+    pub db: DatabasePool,
+    pub partitions: PartitionManager,
+    pub retention_days: u32,
+}
+
+#[async_trait]
+impl Job for MetricRetention {
+    fn name(&self) -> &'static str {
+        "metric_retention"
+    }
+
+    fn description(&self) -> &'static str {
+        "Drop expired request metric partitions and old performance_metrics rows"
+    }
+
+    async fn run(&self) -> anyhow::Result<String> {
+        let (created, dropped) = self.partitions.maintain().await?;
+        let deleted = database::cleanup_old_metrics(&self.db, self.retention_days as i32).await?;
+        Ok(format!(
+            "created {} partitions, dropped {} partitions, deleted {} rows",
+            created, dropped, deleted
+        ))
+    }
+}
+
+/// Updates planner statistics and refreshes materialized views
+pub struct OptimizeDatabase {
+    pub db: DatabasePool,
+}
+
+#[async_trait]
+impl Job for OptimizeDatabase {
+    fn name(&self) -> &'static str {
+        "optimize_database"
+    }
+
+    fn description(&self) -> &'static str {
+        "ANALYZE all tables and refresh materialized views"
+    }
+
+    async fn run(&self) -> anyhow::Result<String> {
+        database::optimize_database(&self.db).await?;
+        Ok("analyzed".to_string())
+    }
+}
+
+/// Refreshes the `performance_summary` materialized view, dropping cached
+/// reports over it
+pub struct RefreshPerformanceSummary {
+    pub db: DatabasePool,
+    pub cache: ResponseCache,
+}
+
+#[async_trait]
+impl Job for RefreshPerformanceSummary {
+    fn name(&self) -> &'static str {
+        "refresh_performance_summary"
+    }
+
+    fn description(&self) -> &'static str {
+        "Refresh the performance_summary materialized view"
+    }
+
+    async fn run(&self) -> anyhow::Result<String> {
+        sqlx::query("SELECT refresh_performance_summary()")
+            .execute(&self.db)
+            .await?;
+        self.cache.invalidate(&[performance::CACHE_TAG]).await;
+        Ok("refreshed".to_string())
+    }
+}
+
+/// Sweeps rate limit keys that leaked without a TTL. The sweep also takes
+/// its own Redis lock, which keeps it exclusive against replicas running an
+/// older release that sweep on their own timer.
+pub struct RateLimitCleanup {
+    pub rate_limiter: RateLimiter,
+}
+
+#[async_trait]
+impl Job for RateLimitCleanup {
+    fn name(&self) -> &'static str {
+        "rate_limit_cleanup"
+    }
+
+    fn description(&self) -> &'static str {
+        "Delete leaked rate limit keys from Redis"
+    }
+
+    async fn run(&self) -> anyhow::Result<String> {
+        if !self.rate_limiter.is_enabled() {
+            return Ok("rate limiting is disabled".to_string());
+        }
+
+        Ok(match self.rate_limiter.cleanup_expired_keys().await? {
+            Some(stats) => format!("deleted {} of {} keys scanned", stats.deleted, stats.scanned),
+            None => "sweep lock held by another replica".to_string(),
+        })
+    }
+}
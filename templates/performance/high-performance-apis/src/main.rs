@@ -1,45 +1,63 @@
 use std::sync::Arc;
-use std::time::Duration;
 
 use axum::{
     extract::State,
-    http::{Method, StatusCode},
+    http::StatusCode,
     response::Json,
     routing::{get, post},
     Router,
 };
+use clap::Parser;
 use tower::ServiceBuilder;
-use tower_http::{
-    compression::CompressionLayer,
-    cors::{Any, CorsLayer},
-    limit::RequestBodyLimitLayer,
-    timeout::TimeoutLayer,
-    trace::TraceLayer,
-};
+use tower_http::trace::TraceLayer;
+use tokio::sync::watch;
 use tracing::{info, warn};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 mod api;
+mod audit;
+mod auth;
+mod batch;
+mod cache;
+mod cli;
+mod client_ip;
 mod config;
 mod database;
 mod error;
 mod graphql;
+mod jobs;
 mod middleware;
 mod metrics;
+mod migrations;
 mod models;
 mod monitoring;
+mod performance;
+mod problem;
 mod rate_limiting;
+mod secrets;
+mod server;
 mod services;
+mod shutdown;
+mod telemetry;
 
 use crate::{
     api::routes,
-    config::Config,
+    audit::AuditLog,
+    auth::{api_keys::{self, ApiKeyStore}, Authenticator, JwtVerifier},
+    cache::ResponseCache,
+    cli::{Cli, Command, ConfigCommand, ConfigFormat, SecretsCommand},
+    client_ip::{ClientIpLayer, TrustedProxies},
+    config::{reload::ConfigReloader, Config, ConfigSources},
     database::DatabasePool,
     error::AppError,
     graphql::create_schema,
-    middleware::{auth::AuthLayer, metrics::MetricsLayer},
-    monitoring::health,
-    rate_limiting::RateLimiter,
+    jobs::{builtin, Schedule, Scheduler},
+    metrics::{partitions::PartitionManager, persistence::RequestRecorder, Metrics},
+    middleware::{audit::AuditLayer, auth::AuthLayer, metrics::MetricsLayer, scope::RequireScopeLayer},
+    monitoring::health::{self, DatabaseCheck, HealthRegistry, RedisCheck},
+    performance::PerformanceAnalytics,
+    rate_limiting::{plans::PlanResolver, RateLimiter},
+    server::tls::TlsReloader,
+    shutdown::Shutdown,
 };
 
 pub type AppState = Arc<AppStateInner>;
@@ -48,22 +66,98 @@ pub type AppState = Arc<AppStateInner>;
 pub struct AppStateInner {
     pub db: DatabasePool,
     pub redis: deadpool_redis::Pool,
-    pub config: Config,
+    /// Current configuration, updated by hot reloads
+    pub config: watch::Receiver<Arc<Config>>,
     pub rate_limiter: RateLimiter,
+    /// JWT and API key authentication
+    pub auth: Authenticator,
+    pub audit: AuditLog,
+    /// Prometheus registry and the request totals behind `/admin/stats`
+    pub metrics: Metrics,
+    /// Sampled request timings bound for `performance_metrics_partitioned`
+    pub request_metrics: RequestRecorder,
+    /// Maintenance jobs, listed and triggered through `/admin/jobs`
+    pub jobs: Arc<Scheduler>,
+    /// Dependency checks behind `/health` and `/health/ready`
+    pub health: Arc<HealthRegistry>,
+    /// Latency reports over `performance_summary`
+    pub performance: PerformanceAnalytics,
+    /// GET responses cached in process and in Redis
+    pub cache: ResponseCache,
     pub graphql_schema: graphql::Schema,
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    // Generating a key must work before any config (or secret) exists
+    if let Some(Command::Secrets { action: SecretsCommand::GenerateKey }) = &cli.command {
+        println!("{}", secrets::generate_key());
+        return Ok(());
+    }
+
     // Initialize configuration
-    let config = Config::from_env()?;
-    
+    let config_sources = cli.config_sources();
+    let config = Config::load(&config_sources)?;
+
+    // Printing the config exits before tracing can write to stdout
+    if let Some(Command::Config { action: ConfigCommand::Print { redact, format } }) = &cli.command {
+        let value = if *redact {
+            config.redacted()?
+        } else {
+            serde_json::to_value(&config)?
+        };
+        match format {
+            ConfigFormat::Yaml => print!("{}", serde_yaml::to_string(&value)?),
+            ConfigFormat::Json => println!("{}", serde_json::to_string_pretty(&value)?),
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Secrets { action: SecretsCommand::Set { name } }) = &cli.command {
+        let provider = secrets::encrypted_provider(&config.secrets)?.ok_or_else(|| {
+            anyhow::anyhow!(
+                "secrets.encrypted_file and the {} environment variable must be set",
+                config.secrets.key_env
+            )
+        })?;
+        let mut value = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut value)?;
+        provider.store(name, value.trim_end_matches(['\r', '\n']))?;
+        return Ok(());
+    }
+
+    // Sized by performance.worker_threads / server.workers
+    let runtime = config.tokio_runtime_config().build()?;
+    runtime.block_on(run(cli, config_sources, config))
+}
+
+async fn run(cli: Cli, config_sources: ConfigSources, config: Config) -> anyhow::Result<()> {
     // Initialize tracing
-    init_tracing(&config)?;
+    let telemetry = telemetry::init_tracing(&config)?;
+
+    // One-off maintenance commands exit without starting the server
+    if let Some(Command::Migrate { action }) = cli.command {
+        let db = database::create_pool(&config.database).await?;
+        return migrations::execute_command(&db, &config.database.migrations_dir, action).await;
+    }
     
     info!("Starting high-performance API server");
     info!("Configuration loaded: {}", config.server.host);
 
+    // Background tasks are registered here so shutdown can stop them
+    let shutdown = Shutdown::new();
+
+    // Hot reload on config file changes and SIGHUP
+    let config_reloader = ConfigReloader::new(config_sources, config.clone());
+    let config_updates = config_reloader.subscribe();
+    shutdown.spawn("config_reloader", async move {
+        if let Err(e) = config_reloader.run().await {
+            warn!("Configuration reloader stopped: {}", e);
+        }
+    });
+    shutdown.spawn("telemetry_config", telemetry.watch_config(config_updates.clone()));
+
     // Initialize database connection pool
     let db = database::create_pool(&config.database).await?;
     info!("Database connection pool created with {} connections", config.database.max_connections);
@@ -73,47 +167,166 @@ async fn main() -> anyhow::Result<()> {
     info!("Redis connection pool created");
 
     // Run database migrations
-    database::run_migrations(&db).await?;
+    database::run_migrations(&db, &config.database.migrations_dir).await?;
     info!("Database migrations completed");
 
-    // Initialize rate limiter
-    let rate_limiter = RateLimiter::new(redis.clone(), config.rate_limiting.clone()).await?;
+    // Initialize metrics
+    let metrics = Metrics::new(&config.metrics)?;
+    info!("Metrics system initialized");
+
+    // Response cache; writes on any replica invalidate entries on all of them
+    let cache = ResponseCache::new(redis.clone(), &config.redis, &config.cache, metrics.clone())?;
+    if config.cache.enabled {
+        shutdown.spawn("cache_invalidations", cache.clone().listen_for_invalidations());
+        info!("Response cache initialized");
+    }
+
+    // Audit events are written in the background, off the request path
+    let (audit, audit_writer) = AuditLog::new(db.clone(), &config.audit, metrics.clone());
+    if let Some(writer) = audit_writer {
+        shutdown.spawn_writer("audit_writer", writer.run());
+        shutdown.spawn("audit_config", audit.clone().watch_config(config_updates.clone()));
+        info!("Audit log initialized");
+    }
+
+    // Request timings are sampled into daily partitions. Today's must exist
+    // before the first write; the metric_retention job keeps them coming.
+    let partitions = PartitionManager::new(db.clone(), &config.metrics);
+    if let Err(e) = partitions.maintain().await {
+        warn!("Request metric partition maintenance failed: {:#}", e);
+    }
+    let (request_metrics, request_metrics_writer) = RequestRecorder::new(db.clone(), &config.metrics, metrics.clone());
+    if let Some(writer) = request_metrics_writer {
+        shutdown.spawn_writer("request_record_writer", writer.run());
+    }
+
+    // API keys, with cache invalidation across replicas and batched last use
+    let api_key_store = ApiKeyStore::new(db.clone(), &config.security);
+    shutdown.spawn("api_key_listener", api_key_store.clone().listen_for_changes());
+    shutdown.spawn(
+        "api_key_last_used_flusher",
+        api_key_store
+            .clone()
+            .run_last_used_flusher(config.security.api_key_last_used_flush_interval),
+    );
+
+    // Initialize rate limiter; only verified API keys get their own buckets
+    let mut rate_limiter = RateLimiter::new(redis.clone(), config.rate_limiting.clone())
+        .await?
+        .with_metrics(metrics.clone())
+        .with_api_keys(api_key_store.clone());
+    if config.rate_limiting.plans_enabled {
+        let plans = PlanResolver::new(db.clone(), &config.rate_limiting);
+        shutdown.spawn("plan_listener", plans.clone().listen_for_changes());
+        rate_limiter = rate_limiter.with_plans(plans);
+    }
+    shutdown.spawn("rate_limiter_config", rate_limiter.clone().watch_config(config_updates.clone()));
     info!("Rate limiter initialized");
 
+    // Maintenance jobs; each runs on one replica at a time
+    let mut scheduler = Scheduler::new(db.clone(), &config.jobs, metrics.clone(), shutdown.clone());
+    scheduler.register(
+        builtin::MetricRetention {
+            db: db.clone(),
+            partitions,
+            retention_days: config.metrics.retention_days,
+        },
+        Schedule::from_config(&config.jobs.metric_retention)?,
+    );
+    scheduler.register(
+        builtin::OptimizeDatabase { db: db.clone() },
+        Schedule::from_config(&config.jobs.optimize_database)?,
+    );
+    scheduler.register(
+        builtin::RefreshPerformanceSummary {
+            db: db.clone(),
+            cache: cache.clone(),
+        },
+        Schedule::from_config(&config.jobs.refresh_performance_summary)?,
+    );
+    scheduler.register(
+        builtin::RateLimitCleanup { rate_limiter: rate_limiter.clone() },
+        Schedule::from_config(&config.rate_limit_cleanup_schedule())?,
+    );
+    let jobs = Arc::new(scheduler);
+    if config.jobs.enabled {
+        jobs.start(&shutdown);
+    }
+
+    // Initialize JWT verification, following key rotations on reload
+    let jwt = JwtVerifier::from_config(&config.security)?;
+    if let Some(remote) = jwt.remote_jwks() {
+        shutdown.spawn("jwks_refresh", remote.run(config.security.jwks_refresh_interval));
+    }
+    shutdown.spawn("jwt_config", jwt.clone().watch_config(config_updates.clone()));
+
+    let auth = Authenticator::new(jwt, api_key_store);
+    info!("Authentication initialized");
+
+    // Dependency checks; only critical ones fail readiness
+    let mut health = HealthRegistry::new(&config.health, shutdown.draining());
+    if config.health.database_check {
+        health.register(DatabaseCheck {
+            db: db.clone(),
+            degraded_latency: config.health.degraded_latency,
+        });
+    }
+    if config.health.redis_check {
+        health.register(RedisCheck {
+            redis: redis.clone(),
+            critical: config.health.redis_critical,
+            degraded_latency: config.health.degraded_latency,
+        });
+    }
+    let health = Arc::new(health);
+
     // Initialize GraphQL schema
-    let graphql_schema = create_schema(db.clone()).await?;
+    let performance = PerformanceAnalytics::new(db.clone(), config_updates.clone());
+    let graphql_schema = create_schema(auth.clone(), audit.clone(), performance.clone(), cache.clone()).await?;
     info!("GraphQL schema created");
 
-    // Initialize metrics
-    metrics::init_metrics(&config.metrics)?;
-    info!("Metrics system initialized");
-
     // Create application state
     let state = Arc::new(AppStateInner {
         db,
         redis,
-        config: config.clone(),
+        config: config_updates,
         rate_limiter,
+        auth,
+        audit,
+        metrics,
+        request_metrics,
+        jobs,
+        health,
+        performance,
+        cache,
         graphql_schema,
     });
 
+    // Collect system and business metrics in the background
+    shutdown.spawn(
+        "metrics_collection",
+        metrics::start_metrics_collection(state.clone(), config.metrics.clone()),
+    );
+
     // Build application with optimized middleware stack
     let app = create_app(state.clone()).await?;
 
     info!("Application created successfully");
 
     // Start metrics server in background
-    let metrics_state = state.clone();
-    tokio::spawn(async move {
-        if let Err(e) = metrics::start_metrics_server(&metrics_state.config.metrics).await {
+    let metrics_config = config.metrics.clone();
+    let metrics_registry = state.metrics.clone();
+    shutdown.spawn("metrics_server", async move {
+        if let Err(e) = metrics::start_metrics_server(&metrics_config, metrics_registry).await {
             warn!("Metrics server error: {}", e);
         }
     });
 
     // Start health check server in background
-    let health_state = state.clone();
-    tokio::spawn(async move {
-        if let Err(e) = health::start_health_server(&health_state.config.health).await {
+    let health_config = config.health.clone();
+    let health_registry = state.health.clone();
+    shutdown.spawn("health_server", async move {
+        if let Err(e) = health::start_health_server(&health_config, health_registry).await {
             warn!("Health check server error: {}", e);
         }
     });
@@ -122,55 +335,104 @@ async fn main() -> anyhow::Result<()> {
     let listener = tokio::net::TcpListener::bind(&format!("{}:{}", config.server.host, config.server.port))
         .await?;
     
-    info!("Server listening on {}:{}", config.server.host, config.server.port);
+    // TLS, with certificates reloaded when they change on disk
+    let tls = if config.server.tls.enabled {
+        let reloader = TlsReloader::new(&config.server.tls, config.performance.enable_http2)?;
+        let acceptor = reloader.acceptor();
+        shutdown.spawn("tls_reloader", async move {
+            if let Err(e) = reloader.run().await {
+                warn!("TLS certificate reloader stopped: {}", e);
+            }
+        });
+        Some(acceptor)
+    } else {
+        None
+    };
+
+    info!(
+        "Server listening on {}://{}:{}",
+        if tls.is_some() { "https" } else { "http" },
+        config.server.host,
+        config.server.port
+    );
     info!("Performance target: {}+ requests/second", config.performance.target_rps);
     info!("Ready to handle high-performance workloads");
 
-    // Start server with optimized configuration
-    axum::serve(listener, app)
-        .tcp_nodelay(true)
-        .tcp_keepalive(Some(Duration::from_secs(60)))
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+    // Connections are configured from server and performance settings. On
+    // SIGTERM readiness fails for server.client_shutdown before the
+    // listener closes and in-flight requests finish.
+    let server = server::serve(
+        listener,
+        app,
+        &config,
+        tls,
+        shutdown.clone().drained(config.server.client_shutdown),
+    );
+
+    // Everything after the signal must finish within server.shutdown_timeout
+    let deadline = shutdown.clone().deadline(config.server.shutdown_timeout);
+    tokio::pin!(deadline);
+
+    tokio::select! {
+        result = server => result?,
+        _ = &mut deadline => {
+            warn!("Shutdown deadline passed with requests still in flight");
+            return Ok(());
+        }
+    }
+
+    // Stop background tasks, let the queue writers flush once the last
+    // handles to their queues (in the state) are gone, then close the pools
+    let cleanup = async move {
+        shutdown.stop_tasks().await;
+        if let Err(e) = state.auth.api_keys().flush_last_used().await {
+            warn!("{:#}", e);
+        }
+
+        let (db, redis) = (state.db.clone(), state.redis.clone());
+        drop(state);
+        shutdown.wait_for_writers().await;
+
+        db.close().await;
+        redis.close();
+    };
+
+    tokio::select! {
+        _ = cleanup => info!("Shutdown complete"),
+        _ = &mut deadline => warn!("Shutdown deadline passed before background tasks finished"),
+    }
 
     Ok(())
 }
 
 async fn create_app(state: AppState) -> anyhow::Result<Router> {
+    let trusted_proxies = TrustedProxies::from_config(&state.config.borrow().security.trusted_proxies)?;
+
     // Performance-optimized middleware stack
     let middleware_stack = ServiceBuilder::new()
-        // Compression for response optimization
-        .layer(CompressionLayer::new())
-        // Request timeout to prevent resource exhaustion
-        .layer(TimeoutLayer::new(Duration::from_secs(30)))
-        // Request body size limit for security
-        .layer(RequestBodyLimitLayer::new(16 * 1024 * 1024)) // 16MB
-        // CORS configuration
-        .layer(
-            CorsLayer::new()
-                .allow_origin(Any)
-                .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
-                .allow_headers(Any)
-        )
         // Distributed tracing
         .layer(TraceLayer::new_for_http())
         // Custom metrics collection
-        .layer(MetricsLayer::new())
+        .layer(MetricsLayer::new(state.metrics.clone(), state.request_metrics.clone()))
+        // Resolve the client address, honoring only trusted proxies
+        .layer(ClientIpLayer::new(trusted_proxies))
         // Rate limiting middleware
         .layer(rate_limiting::RateLimitingLayer::new(state.rate_limiter.clone()));
 
     // Build router with all endpoints
+    let admin_routes = create_admin_routes(state.auth.clone(), state.audit.clone(), state.cache.clone());
     let api_routes = routes::create_routes();
     let graphql_routes = graphql::create_routes(state.graphql_schema.clone());
 
     let app = Router::new()
         // Health check endpoints (no auth required)
-        .route("/health", get(health::health_check))
-        .route("/health/ready", get(health::readiness_check))
-        .route("/health/live", get(health::liveness_check))
+        .merge(health::routes(state.health.clone()))
+        
+        // Public keys for verifying our tokens (no auth required)
+        .route("/.well-known/jwks.json", get(auth::jwks))
         
         // Metrics endpoint (no auth required)
-        .route("/metrics", get(metrics::metrics_handler))
+        .route("/metrics", get(metrics::metrics_handler).with_state(state.metrics.clone()))
         
         // API documentation
         .merge(create_docs_routes())
@@ -182,7 +444,7 @@ async fn create_app(state: AppState) -> anyhow::Result<Router> {
         .nest("/api/v1", api_routes)
         
         // Protected admin routes
-        .nest("/admin", create_admin_routes())
+        .nest("/admin", admin_routes)
         
         // Global error handler
         .fallback(handle_404)
@@ -191,9 +453,10 @@ async fn create_app(state: AppState) -> anyhow::Result<Router> {
         .layer(middleware_stack)
         
         // Add application state
-        .with_state(state);
+        .with_state(state.clone());
 
-    Ok(app)
+    // Compression, request timeout, body limit and CORS, outermost
+    Ok(server::http_layers(app, &state.config))
 }
 
 fn create_docs_routes() -> Router<AppState> {
@@ -226,11 +489,18 @@ fn create_docs_routes() -> Router<AppState> {
         .url("/docs/openapi.json", ApiDoc::openapi())
 }
 
-fn create_admin_routes() -> Router<AppState> {
+fn create_admin_routes(auth: Authenticator, audit: AuditLog, cache: ResponseCache) -> Router<AppState> {
+    let admin_read = RequireScopeLayer::new(["admin:read"]);
+
     Router::new()
-        .route("/stats", get(admin_stats))
-        .route("/config", get(admin_config))
-        .layer(AuthLayer::new()) // Require authentication for admin routes
+        .route("/stats", get(admin_stats).layer(admin_read.clone()))
+        .route("/config", get(admin_config).layer(admin_read))
+        .nest("/api-keys", api_keys::routes(cache.clone()))
+        .nest("/audit", audit::routes())
+        .nest("/jobs", jobs::routes())
+        .nest("/performance", performance::routes(cache))
+        .layer(AuditLayer::new(audit.clone())) // Record every admin request
+        .layer(AuthLayer::new(auth, audit)) // Require authentication for admin routes
 }
 
 async fn admin_stats(State(state): State<AppState>) -> Result<Json<serde_json::Value>, AppError> {
@@ -241,29 +511,30 @@ async fn admin_stats(State(state): State<AppState>) -> Result<Json<serde_json::V
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs(),
-        "memory_usage": get_memory_usage(),
-        "performance_metrics": metrics::get_current_metrics().await,
+        "process": state.metrics.process(),
+        "performance_metrics": state.metrics.snapshot(),
     });
 
     Ok(Json(stats))
 }
 
 async fn admin_config(State(state): State<AppState>) -> Result<Json<serde_json::Value>, AppError> {
+    let config = state.config.borrow().clone();
     let config_summary = serde_json::json!({
         "server": {
-            "host": state.config.server.host,
-            "port": state.config.server.port,
+            "host": config.server.host,
+            "port": config.server.port,
         },
         "database": {
-            "max_connections": state.config.database.max_connections,
+            "max_connections": config.database.max_connections,
         },
         "performance": {
-            "target_rps": state.config.performance.target_rps,
-            "max_response_time_ms": state.config.performance.max_response_time_ms,
+            "target_rps": config.performance.target_rps,
+            "max_response_time_ms": config.performance.max_response_time_ms,
         },
         "rate_limiting": {
-            "requests_per_second": state.config.rate_limiting.requests_per_second,
-            "burst_size": state.config.rate_limiting.burst_size,
+            "requests_per_second": config.rate_limiting.requests_per_second,
+            "burst_size": config.rate_limiting.burst_size,
         }
     });
 
@@ -279,89 +550,3 @@ async fn handle_404() -> (StatusCode, Json<serde_json::Value>) {
 
     (StatusCode::NOT_FOUND, Json(error_response))
 }
-
-fn get_memory_usage() -> serde_json::Value {
-    #[cfg(feature = "jemalloc")]
-    {
-        use jemalloc_ctl::{stats, epoch};
-        
-        if let (Ok(_), Ok(allocated), Ok(resident)) = (
-            epoch::advance(),
-            stats::allocated::read(),
-            stats::resident::read()
-        ) {
-            return serde_json::json!({
-                "allocated_bytes": allocated,
-                "resident_bytes": resident,
-                "allocator": "jemalloc"
-            });
-        }
-    }
-
-    serde_json::json!({
-        "allocated_bytes": "unknown",
-        "resident_bytes": "unknown", 
-        "allocator": "system"
-    })
-}
-
-fn init_tracing(config: &Config) -> anyhow::Result<()> {
-    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| format!("{}=info,tower_http=debug", env!("CARGO_PKG_NAME")).into());
-
-    let subscriber = tracing_subscriber::registry()
-        .with(filter)
-        .with(tracing_subscriber::fmt::layer().json());
-
-    // Add OpenTelemetry if configured
-    if config.tracing.enabled {
-        let tracer = opentelemetry_jaeger::new_agent_pipeline()
-            .with_service_name(&config.tracing.service_name)
-            .install_batch(opentelemetry::runtime::Tokio)?;
-
-        let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
-        subscriber.with(telemetry).init();
-    } else {
-        subscriber.init();
-    }
-
-    Ok(())
-}
-
-async fn shutdown_signal() {
-    use tokio::signal;
-
-    let ctrl_c = async {
-        signal::ctrl_c()
-            .await
-            .expect("failed to install Ctrl+C handler");
-    };
-
-    #[cfg(unix)]
-    let terminate = async {
-        signal::unix::signal(signal::unix::SignalKind::terminate())
-            .expect("failed to install signal handler")
-            .recv()
-            .await;
-    };
-
-    #[cfg(not(unix))]
-    let terminate = std::future::pending::<()>();
-
-    tokio::select! {
-        _ = ctrl_c => {
-            info!("Received Ctrl+C, shutting down gracefully");
-        },
-        _ = terminate => {
-            info!("Received terminate signal, shutting down gracefully");
-        },
-    }
-
-    // Perform graceful shutdown tasks
-    info!("Performing graceful shutdown...");
-    
-    // Give ongoing requests time to complete
-    tokio::time::sleep(Duration::from_secs(1)).await;
-    
-    info!("Shutdown complete");
-}
\ No newline at end of file
@@ -1,281 +1,553 @@
 use axum::{extract::State, http::StatusCode, response::Response};
-use metrics::{counter, gauge, histogram, register_counter, register_gauge, register_histogram};
-use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
-use std::{sync::Arc, time::Instant};
-use tokio::time::{Duration, interval};
-use tracing::{error, info, warn};
+use metrics::{Counter, Gauge, Histogram, Key, KeyName, Label, Level, Metadata, Recorder, SharedString};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle, PrometheusRecorder};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Instant,
+};
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use tracing::{debug, info, warn};
 
 use crate::{config::MetricsConfig, AppState};
 
-static mut PROMETHEUS_HANDLE: Option<PrometheusHandle> = None;
-
-/// Initialize the metrics system
-pub fn init_metrics(config: &MetricsConfig) -> anyhow::Result<()> {
-    if !config.enabled {
-        info!("Metrics collection is disabled");
-        return Ok(());
-    }
-
-    info!("Initializing metrics collection");
-
-    let builder = PrometheusBuilder::new();
-    let handle = builder.install()?;
-
-    unsafe {
-        PROMETHEUS_HANDLE = Some(handle);
+pub mod partitions;
+pub mod persistence;
+pub mod process;
+
+use self::process::ProcessStats;
+
+/// `path` label of requests that matched no route
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+/// `path` label of routes left out by the allowlist or the label limit
+pub const OVERFLOW_ROUTE: &str = "other";
+
+/// `method` label of HTTP metrics: the standard methods, or `other` for
+/// extension methods, which clients can make up freely
+fn method_label(method: &str) -> &'static str {
+    match method {
+        "GET" => "GET",
+        "HEAD" => "HEAD",
+        "POST" => "POST",
+        "PUT" => "PUT",
+        "PATCH" => "PATCH",
+        "DELETE" => "DELETE",
+        "OPTIONS" => "OPTIONS",
+        _ => "other",
     }
+}
 
-    // Register core metrics
-    register_core_metrics();
+static METADATA: Metadata<'static> = Metadata::new(module_path!(), Level::INFO, Some(module_path!()));
 
-    info!("Metrics system initialized successfully");
-    Ok(())
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
 }
 
-/// Register core application metrics
-fn register_core_metrics() {
+/// Core application metrics and their descriptions
+const CORE_METRICS: &[(Kind, &str, &str)] = &[
     // HTTP request metrics
-    register_counter!("http_requests_total", "Total number of HTTP requests");
-    register_histogram!("http_request_duration_seconds", "HTTP request duration in seconds");
-    register_counter!("http_requests_errors_total", "Total number of HTTP request errors");
-
+    (Kind::Counter, "http_requests_total", "Total number of HTTP requests"),
+    (Kind::Histogram, "http_request_duration_seconds", "HTTP request duration in seconds"),
+    (Kind::Counter, "http_requests_errors_total", "Total number of HTTP request errors"),
     // Database metrics
-    register_gauge!("database_connections_active", "Number of active database connections");
-    register_gauge!("database_connections_idle", "Number of idle database connections");
-    register_histogram!("database_query_duration_seconds", "Database query duration in seconds");
-    register_counter!("database_queries_total", "Total number of database queries");
-
+    (Kind::Gauge, "database_connections_active", "Number of active database connections"),
+    (Kind::Gauge, "database_connections_idle", "Number of idle database connections"),
+    (Kind::Histogram, "database_query_duration_seconds", "Database query duration in seconds"),
+    (Kind::Counter, "database_queries_total", "Total number of database queries"),
     // Redis metrics
-    register_gauge!("redis_connections_active", "Number of active Redis connections");
-    register_counter!("redis_commands_total", "Total number of Redis commands");
-    register_histogram!("redis_command_duration_seconds", "Redis command duration in seconds");
-
+    (Kind::Gauge, "redis_connections_active", "Number of active Redis connections"),
+    (Kind::Counter, "redis_commands_total", "Total number of Redis commands"),
+    (Kind::Histogram, "redis_command_duration_seconds", "Redis command duration in seconds"),
     // Rate limiting metrics
-    register_counter!("rate_limit_hits_total", "Total number of rate limit hits");
-    register_counter!("rate_limit_misses_total", "Total number of requests allowed");
-
+    (Kind::Counter, "rate_limit_hits_total", "Total number of rate limit hits"),
+    (Kind::Counter, "rate_limit_misses_total", "Total number of requests allowed"),
+    (Kind::Counter, "rate_limit_sweeper_keys_scanned_total", "Total number of rate limit keys scanned by the sweeper"),
+    (Kind::Counter, "rate_limit_sweeper_keys_deleted_total", "Total number of leaked rate limit keys deleted by the sweeper"),
+    (Kind::Histogram, "rate_limit_sweeper_duration_seconds", "Rate limit sweep duration in seconds"),
+    // Request record persistence metrics
+    (Kind::Counter, "request_records_written_total", "Total number of request records written to the database"),
+    (Kind::Counter, "request_records_dropped_total", "Total number of sampled request records dropped"),
+    // Response cache metrics
+    (Kind::Counter, "http_cache_requests_total", "Total number of cacheable requests by cache result"),
+    (Kind::Counter, "http_cache_invalidations_total", "Total number of cache tag invalidations"),
+    // Job metrics
+    (Kind::Counter, "job_runs_total", "Total number of maintenance job runs"),
+    (Kind::Histogram, "job_duration_seconds", "Maintenance job duration in seconds"),
+    // Audit log metrics
+    (Kind::Counter, "audit_events_written_total", "Total number of audit events written"),
+    (Kind::Counter, "audit_events_dropped_total", "Total number of audit events dropped"),
     // Performance metrics
-    register_gauge!("memory_usage_bytes", "Memory usage in bytes");
-    register_gauge!("cpu_usage_percentage", "CPU usage percentage");
-    register_gauge!("requests_per_second", "Current requests per second");
-
+    (Kind::Gauge, "memory_usage_bytes", "Memory usage in bytes"),
+    (Kind::Gauge, "cpu_usage_percentage", "CPU usage percentage"),
+    (Kind::Gauge, "requests_per_second", "Current requests per second"),
+    // Process metrics
+    (Kind::Gauge, "process_resident_memory_bytes", "Resident memory size in bytes"),
+    (Kind::Gauge, "process_cpu_seconds_total", "Total user and system CPU time in seconds"),
+    (Kind::Gauge, "process_open_fds", "Number of open file descriptors"),
+    (Kind::Gauge, "process_threads", "Number of OS threads"),
+    (Kind::Gauge, "container_memory_limit_bytes", "Container memory limit (cgroup v2 memory.max) in bytes"),
     // GraphQL metrics
-    register_counter!("graphql_queries_total", "Total number of GraphQL queries");
-    register_histogram!("graphql_query_duration_seconds", "GraphQL query duration in seconds");
-    register_counter!("graphql_errors_total", "Total number of GraphQL errors");
-
+    (Kind::Counter, "graphql_queries_total", "Total number of GraphQL queries"),
+    (Kind::Histogram, "graphql_query_duration_seconds", "GraphQL query duration in seconds"),
+    (Kind::Counter, "graphql_errors_total", "Total number of GraphQL errors"),
     // Business metrics
-    register_gauge!("active_users", "Number of currently active users");
-    register_counter!("api_operations_total", "Total number of API operations");
-}
-
-/// Start the metrics collection server
-pub async fn start_metrics_server(config: &MetricsConfig) -> anyhow::Result<()> {
-    if !config.enabled {
-        return Ok(());
+    (Kind::Gauge, "active_users", "Number of currently active users"),
+    (Kind::Counter, "api_operations_total", "Total number of API operations"),
+];
+
+fn describe_core_metrics(recorder: &PrometheusRecorder) {
+    for (kind, name, description) in CORE_METRICS {
+        let name = KeyName::from_const_str(name);
+        let description = SharedString::const_str(description);
+        match kind {
+            Kind::Counter => recorder.describe_counter(name, None, description),
+            Kind::Gauge => recorder.describe_gauge(name, None, description),
+            Kind::Histogram => recorder.describe_histogram(name, None, description),
+        }
     }
+}
 
-    let bind_addr = format!("{}:{}", config.host, config.port);
-    info!("Starting metrics server on {}", bind_addr);
+fn key(name: &'static str, labels: &[(&'static str, &str)]) -> Key {
+    let labels: Vec<Label> = labels
+        .iter()
+        .map(|(key, value)| Label::new(*key, value.to_string()))
+        .collect();
+    Key::from_parts(name, labels)
+}
 
-    let app = axum::Router::new()
-        .route(&config.path, axum::routing::get(metrics_handler));
+/// Where a rate limit decision was made
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitBackend {
+    Redis,
+    Fallback,
+}
 
-    let listener = tokio::net::TcpListener::bind(&bind_addr).await?;
-    
-    axum::serve(listener, app)
-        .await?;
+impl RateLimitBackend {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Redis => "redis",
+            Self::Fallback => "fallback",
+        }
+    }
+}
 
-    Ok(())
+/// How the response cache answered a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheResult {
+    /// Served from the in-process cache
+    L1Hit,
+    /// Served from Redis
+    L2Hit,
+    /// Filled from the handler
+    Miss,
+    /// Not cacheable, or the client asked to skip the cache
+    Bypass,
 }
 
-/// Metrics endpoint handler
-pub async fn metrics_handler() -> Result<Response<String>, StatusCode> {
-    unsafe {
-        if let Some(handle) = &PROMETHEUS_HANDLE {
-            let metrics = handle.render();
-            Ok(Response::builder()
-                .header("content-type", "text/plain; version=0.0.4")
-                .body(metrics)
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?)
-        } else {
-            Err(StatusCode::SERVICE_UNAVAILABLE)
+impl CacheResult {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::L1Hit => "l1_hit",
+            Self::L2Hit => "l2_hit",
+            Self::Miss => "miss",
+            Self::Bypass => "bypass",
         }
     }
 }
 
-/// Record HTTP request metrics
-pub fn record_http_request(
-    method: &str,
-    path: &str,
-    status_code: u16,
-    duration: Duration,
-) {
-    let labels = [
-        ("method", method),
-        ("path", path),
-        ("status", &status_code.to_string()),
-    ];
-
-    counter!("http_requests_total", &labels).increment(1);
-    histogram!("http_request_duration_seconds", &labels).record(duration.as_secs_f64());
-
-    if status_code >= 400 {
-        counter!("http_requests_errors_total", &labels).increment(1);
-    }
+/// Keeps the `path` label of HTTP metrics to a bounded set of route
+/// templates, so a misconfigured route can't create a series per request
+#[derive(Default)]
+struct RouteLabels {
+    allowlist: HashSet<String>,
+    max: usize,
+    seen: RwLock<HashSet<String>>,
 }
 
-/// Record database metrics
-pub fn record_database_query(query_type: &str, duration: Duration, success: bool) {
-    let labels = [
-        ("query_type", query_type),
-        ("success", if success { "true" } else { "false" }),
-    ];
+impl RouteLabels {
+    fn new(config: &MetricsConfig) -> Self {
+        Self {
+            allowlist: config.route_labels.iter().cloned().collect(),
+            max: config.max_route_labels,
+            seen: RwLock::default(),
+        }
+    }
+
+    fn label<'a>(&self, route: &'a str) -> &'a str {
+        if route == UNMATCHED_ROUTE {
+            return route;
+        }
+        if !self.allowlist.is_empty() && !self.allowlist.contains(route) {
+            return OVERFLOW_ROUTE;
+        }
+        if self.seen.read().unwrap().contains(route) {
+            return route;
+        }
 
-    counter!("database_queries_total", &labels).increment(1);
-    histogram!("database_query_duration_seconds", &labels).record(duration.as_secs_f64());
+        let mut seen = self.seen.write().unwrap();
+        if seen.len() < self.max {
+            seen.insert(route.to_string());
+            route
+        } else if seen.contains(route) {
+            route
+        } else {
+            OVERFLOW_ROUTE
+        }
+    }
 }
 
-/// Record Redis metrics
-pub fn record_redis_command(command: &str, duration: Duration, success: bool) {
-    let labels = [
-        ("command", command),
-        ("success", if success { "true" } else { "false" }),
-    ];
+/// Totals kept alongside the exported metrics for `/admin/stats`
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsSnapshot {
+    pub total_requests: u64,
+    pub error_requests: u64,
+    pub requests_per_second: f64,
+    pub cpu_usage_percentage: f64,
+}
 
-    counter!("redis_commands_total", &labels).increment(1);
-    histogram!("redis_command_duration_seconds", &labels).record(duration.as_secs_f64());
+/// The last process stats read, and CPU usage since the one before
+struct ProcessSample {
+    at: Instant,
+    stats: ProcessStats,
+    cpu_usage_percentage: f64,
 }
 
-/// Record rate limiting metrics
-pub fn record_rate_limit_hit(identifier: &str) {
-    let labels = [("identifier_type", identifier)];
-    counter!("rate_limit_hits_total", &labels).increment(1);
+#[derive(Default)]
+struct Registry {
+    /// Absent when metrics export is disabled
+    prometheus: Option<(PrometheusRecorder, PrometheusHandle)>,
+    routes: RouteLabels,
+    requests: AtomicU64,
+    errors: AtomicU64,
+    /// f64 bits
+    requests_per_second: AtomicU64,
+    /// When the request rate was last computed, and the total at that time
+    last_rate_sample: Mutex<Option<(Instant, u64)>>,
+    process: Mutex<Option<ProcessSample>>,
 }
 
-pub fn record_rate_limit_miss(identifier: &str) {
-    let labels = [("identifier_type", identifier)];
-    counter!("rate_limit_misses_total", &labels).increment(1);
+/// The application's metrics, held in [`AppStateInner`](crate::AppStateInner)
+/// and handed to whatever records them. Each handle built with
+/// [`Metrics::prometheus`] has its own registry instead of a global
+/// recorder, so tests can record into one and read it back in isolation.
+///
+/// Labels are bounded: HTTP metrics are keyed by route template (see
+/// [`UNMATCHED_ROUTE`] and [`OVERFLOW_ROUTE`]) and other labels only take
+/// values fixed in code, never client input.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Registry>,
 }
 
-/// Record GraphQL metrics
-pub fn record_graphql_query(query_name: &str, duration: Duration, success: bool) {
-    let labels = [
-        ("query", query_name),
-        ("success", if success { "true" } else { "false" }),
-    ];
+impl Metrics {
+    pub fn new(config: &MetricsConfig) -> anyhow::Result<Self> {
+        if !config.enabled {
+            info!("Metrics collection is disabled");
+            return Ok(Self::disabled());
+        }
+        Self::prometheus(config)
+    }
 
-    counter!("graphql_queries_total", &labels).increment(1);
-    histogram!("graphql_query_duration_seconds", &labels).record(duration.as_secs_f64());
+    /// Metrics exported in the Prometheus text format, whether or not
+    /// `config.enabled` is set
+    pub fn prometheus(config: &MetricsConfig) -> anyhow::Result<Self> {
+        let buckets: Vec<f64> = config.latency_buckets_ms.iter().map(|ms| ms / 1000.0).collect();
+        let recorder = PrometheusBuilder::new()
+            .set_buckets_for_metric(Matcher::Full("http_request_duration_seconds".to_string()), &buckets)?
+            .build_recorder();
+        let handle = recorder.handle();
+        describe_core_metrics(&recorder);
+
+        Ok(Self {
+            inner: Arc::new(Registry {
+                prometheus: Some((recorder, handle)),
+                routes: RouteLabels::new(config),
+                ..Default::default()
+            }),
+        })
+    }
 
-    if !success {
-        counter!("graphql_errors_total", &labels).increment(1);
+    /// Metrics that only keep the [`MetricsSnapshot`] totals
+    pub fn disabled() -> Self {
+        Self::default()
     }
-}
 
-/// Update system resource metrics
-pub fn update_system_metrics(state: &AppState) {
-    // Database connection metrics
-    let db_stats = state.db.size();
-    let db_idle = state.db.num_idle();
-    gauge!("database_connections_active").set((db_stats - db_idle) as f64);
-    gauge!("database_connections_idle").set(db_idle as f64);
+    /// Everything recorded, in the Prometheus text format
+    pub fn render(&self) -> Option<String> {
+        self.inner.prometheus.as_ref().map(|(_, handle)| handle.render())
+    }
 
-    // Redis connection metrics  
-    let redis_status = state.redis.status();
-    gauge!("redis_connections_active").set((redis_status.size - redis_status.available) as f64);
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let inner = &self.inner;
+        MetricsSnapshot {
+            total_requests: inner.requests.load(Ordering::Relaxed),
+            error_requests: inner.errors.load(Ordering::Relaxed),
+            requests_per_second: f64::from_bits(inner.requests_per_second.load(Ordering::Relaxed)),
+            cpu_usage_percentage: inner
+                .process
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map_or(0.0, |sample| sample.cpu_usage_percentage),
+        }
+    }
 
-    // Memory usage (if jemalloc is available)
-    if let Ok(memory_usage) = get_memory_usage() {
-        gauge!("memory_usage_bytes").set(memory_usage as f64);
+    /// The process stats from the last collection, if they could be read
+    pub fn process(&self) -> Option<ProcessStats> {
+        self.inner.process.lock().unwrap().as_ref().map(|sample| sample.stats.clone())
     }
 
-    // CPU usage (simplified)
-    if let Ok(cpu_usage) = get_cpu_usage() {
-        gauge!("cpu_usage_percentage").set(cpu_usage);
+    fn counter(&self, name: &'static str, labels: &[(&'static str, &str)]) -> Counter {
+        match &self.inner.prometheus {
+            Some((recorder, _)) => recorder.register_counter(&key(name, labels), &METADATA),
+            None => Counter::noop(),
+        }
     }
-}
 
-/// Get current memory usage in bytes
-#[cfg(feature = "jemalloc")]
-fn get_memory_usage() -> anyhow::Result<usize> {
-    use jemalloc_ctl::{stats, epoch};
-    
-    epoch::advance()?;
-    let allocated = stats::allocated::read()?;
-    Ok(allocated)
-}
+    fn gauge(&self, name: &'static str, labels: &[(&'static str, &str)]) -> Gauge {
+        match &self.inner.prometheus {
+            Some((recorder, _)) => recorder.register_gauge(&key(name, labels), &METADATA),
+            None => Gauge::noop(),
+        }
+    }
 
-#[cfg(not(feature = "jemalloc"))]
-fn get_memory_usage() -> anyhow::Result<usize> {
-    // Fallback: return 0 if jemalloc is not available
-    Ok(0)
-}
+    fn histogram(&self, name: &'static str, labels: &[(&'static str, &str)]) -> Histogram {
+        match &self.inner.prometheus {
+            Some((recorder, _)) => recorder.register_histogram(&key(name, labels), &METADATA),
+            None => Histogram::noop(),
+        }
+    }
 
-/// Get current CPU usage percentage (simplified implementation)
-fn get_cpu_usage() -> anyhow::Result<f64> {
-    // This is a simplified implementation
-    // In production, you might want to use a more sophisticated approach
-    // like reading from /proc/stat on Linux or using system APIs
-    
-    static mut LAST_MEASUREMENT: Option<Instant> = None;
-    static mut LAST_CPU_TIME: std::time::Duration = std::time::Duration::ZERO;
-    
-    unsafe {
-        let now = Instant::now();
-        if let Some(last_time) = LAST_MEASUREMENT {
-            let elapsed = now.duration_since(last_time);
-            if elapsed > Duration::from_secs(1) {
-                LAST_MEASUREMENT = Some(now);
-                // This is a placeholder - implement actual CPU measurement
-                return Ok(50.0); // Return 50% as placeholder
-            }
-        } else {
-            LAST_MEASUREMENT = Some(now);
+    /// Record HTTP request metrics. `route` is the matched route template,
+    /// or [`UNMATCHED_ROUTE`]; methods outside the standard set are
+    /// labelled `other`.
+    pub fn record_http_request(&self, method: &str, route: &str, status_code: u16, duration: Duration) {
+        self.inner.requests.fetch_add(1, Ordering::Relaxed);
+        if status_code >= 400 {
+            self.inner.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.inner.prometheus.is_none() {
+            return;
+        }
+
+        let status = status_code.to_string();
+        let path = self.inner.routes.label(route);
+        let labels = [("method", method_label(method)), ("path", path), ("status", status.as_str())];
+
+        self.counter("http_requests_total", &labels).increment(1);
+        self.histogram("http_request_duration_seconds", &labels).record(duration.as_secs_f64());
+        if status_code >= 400 {
+            self.counter("http_requests_errors_total", &labels).increment(1);
         }
     }
-    
-    Ok(0.0)
-}
 
-/// Performance metrics collector
-pub struct PerformanceCollector {
-    request_count: Arc<std::sync::atomic::AtomicU64>,
-    last_measurement: Arc<std::sync::Mutex<Instant>>,
-}
+    /// Record database metrics
+    pub fn record_database_query(&self, query_type: &'static str, duration: Duration, success: bool) {
+        let labels = [
+            ("query_type", query_type),
+            ("success", if success { "true" } else { "false" }),
+        ];
 
-impl PerformanceCollector {
-    pub fn new() -> Self {
-        Self {
-            request_count: Arc::new(std::sync::atomic::AtomicU64::new(0)),
-            last_measurement: Arc::new(std::sync::Mutex::new(Instant::now())),
+        self.counter("database_queries_total", &labels).increment(1);
+        self.histogram("database_query_duration_seconds", &labels).record(duration.as_secs_f64());
+    }
+
+    /// Record Redis metrics
+    pub fn record_redis_command(&self, command: &'static str, duration: Duration, success: bool) {
+        let labels = [
+            ("command", command),
+            ("success", if success { "true" } else { "false" }),
+        ];
+
+        self.counter("redis_commands_total", &labels).increment(1);
+        self.histogram("redis_command_duration_seconds", &labels).record(duration.as_secs_f64());
+    }
+
+    /// Record a rate limit decision: a miss when allowed, a hit when limited
+    pub fn record_rate_limit(&self, backend: RateLimitBackend, allowed: bool) {
+        let name = if allowed { "rate_limit_misses_total" } else { "rate_limit_hits_total" };
+        self.counter(name, &[("backend", backend.as_str())]).increment(1);
+    }
+
+    /// Record one rate limit sweeper run
+    pub fn record_rate_limit_sweep(&self, scanned: u64, deleted: u64, duration: Duration) {
+        self.counter("rate_limit_sweeper_keys_scanned_total", &[]).increment(scanned);
+        self.counter("rate_limit_sweeper_keys_deleted_total", &[]).increment(deleted);
+        self.histogram("rate_limit_sweeper_duration_seconds", &[]).record(duration.as_secs_f64());
+    }
+
+    /// Record request records written in one batch, or dropped
+    pub fn record_request_records(&self, written: u64, dropped: u64) {
+        self.counter("request_records_written_total", &[]).increment(written);
+        self.counter("request_records_dropped_total", &[]).increment(dropped);
+    }
+
+    /// Record how the response cache answered a request
+    pub fn record_cache(&self, result: CacheResult) {
+        self.counter("http_cache_requests_total", &[("result", result.as_str())]).increment(1);
+    }
+
+    /// Record invalidated cache tags
+    pub fn record_cache_invalidation(&self, tags: u64) {
+        self.counter("http_cache_invalidations_total", &[]).increment(tags);
+    }
+
+    /// Record one maintenance job run
+    pub fn record_job_run(&self, job: &str, duration: Duration, success: bool) {
+        let labels = [
+            ("job", job),
+            ("success", if success { "true" } else { "false" }),
+        ];
+
+        self.counter("job_runs_total", &labels).increment(1);
+        self.histogram("job_duration_seconds", &labels).record(duration.as_secs_f64());
+    }
+
+    /// Record audit events written in one batch, or dropped
+    pub fn record_audit_events(&self, written: u64, dropped: u64) {
+        self.counter("audit_events_written_total", &[]).increment(written);
+        self.counter("audit_events_dropped_total", &[]).increment(dropped);
+    }
+
+    /// Record GraphQL metrics
+    pub fn record_graphql_query(&self, query_name: &'static str, duration: Duration, success: bool) {
+        let labels = [
+            ("query", query_name),
+            ("success", if success { "true" } else { "false" }),
+        ];
+
+        self.counter("graphql_queries_total", &labels).increment(1);
+        self.histogram("graphql_query_duration_seconds", &labels).record(duration.as_secs_f64());
+
+        if !success {
+            self.counter("graphql_errors_total", &labels).increment(1);
         }
     }
 
-    pub fn record_request(&self) {
-        self.request_count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    /// Record connection pool usage
+    pub fn record_pools(&self, database_active: usize, database_idle: usize, redis_active: usize) {
+        self.gauge("database_connections_active", &[]).set(database_active as f64);
+        self.gauge("database_connections_idle", &[]).set(database_idle as f64);
+        self.gauge("redis_connections_active", &[]).set(redis_active as f64);
     }
 
-    pub fn calculate_rps(&self) -> f64 {
-        let mut last_time = self.last_measurement.lock().unwrap();
+    /// Record process resource usage, and CPU usage since the last call
+    pub fn record_process(&self, stats: ProcessStats) {
         let now = Instant::now();
-        let elapsed = now.duration_since(*last_time);
-        
-        if elapsed >= Duration::from_secs(1) {
-            let count = self.request_count.swap(0, std::sync::atomic::Ordering::Relaxed);
-            *last_time = now;
-            
-            let rps = count as f64 / elapsed.as_secs_f64();
-            gauge!("requests_per_second").set(rps);
-            
-            rps
-        } else {
-            0.0
+        let mut last = self.inner.process.lock().unwrap();
+        let cpu_usage_percentage = match last.as_ref() {
+            Some(previous) => {
+                let elapsed = now.duration_since(previous.at).as_secs_f64();
+                let cpu = (stats.cpu_seconds - previous.stats.cpu_seconds).max(0.0);
+                if elapsed > 0.0 { cpu / elapsed * 100.0 } else { previous.cpu_usage_percentage }
+            }
+            None => 0.0,
+        };
+
+        let resident = stats.resident_memory_bytes as f64;
+        self.gauge("process_resident_memory_bytes", &[]).set(resident);
+        self.gauge("memory_usage_bytes", &[]).set(resident);
+        self.gauge("process_cpu_seconds_total", &[]).set(stats.cpu_seconds);
+        self.gauge("process_open_fds", &[]).set(stats.open_fds as f64);
+        self.gauge("process_threads", &[]).set(stats.threads as f64);
+        if let Some(limit) = stats.memory_limit_bytes {
+            self.gauge("container_memory_limit_bytes", &[]).set(limit as f64);
+        }
+        self.gauge("cpu_usage_percentage", &[]).set(cpu_usage_percentage);
+
+        *last = Some(ProcessSample {
+            at: now,
+            stats,
+            cpu_usage_percentage,
+        });
+    }
+
+    pub fn record_active_users(&self, count: i64) {
+        self.gauge("active_users", &[]).set(count as f64);
+    }
+
+    pub fn record_api_operations(&self, count: u64) {
+        self.counter("api_operations_total", &[]).increment(count);
+    }
+
+    /// Update `requests_per_second` from the requests recorded since the
+    /// last update
+    pub fn update_request_rate(&self) {
+        let now = Instant::now();
+        let total = self.inner.requests.load(Ordering::Relaxed);
+        let mut last = self.inner.last_rate_sample.lock().unwrap();
+
+        if let Some((at, previous)) = last.replace((now, total)) {
+            let elapsed = now.duration_since(at).as_secs_f64();
+            if elapsed > 0.0 {
+                let rps = total.saturating_sub(previous) as f64 / elapsed;
+                self.inner.requests_per_second.store(rps.to_bits(), Ordering::Relaxed);
+                self.gauge("requests_per_second", &[]).set(rps);
+            }
         }
     }
 }
 
-/// Start background metrics collection
+/// Start the metrics collection server
+pub async fn start_metrics_server(config: &MetricsConfig, metrics: Metrics) -> anyhow::Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+
+    let bind_addr = format!("{}:{}", config.host, config.port);
+    info!("Starting metrics server on {}", bind_addr);
+
+    let app = axum::Router::new()
+        .route(&config.path, axum::routing::get(metrics_handler))
+        .with_state(metrics);
+
+    let listener = tokio::net::TcpListener::bind(&bind_addr).await?;
+    
+    axum::serve(listener, app)
+        .await?;
+
+    Ok(())
+}
+
+/// Metrics endpoint handler
+pub async fn metrics_handler(State(metrics): State<Metrics>) -> Result<Response<String>, StatusCode> {
+    let metrics = metrics.render().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    Response::builder()
+        .header("content-type", "text/plain; version=0.0.4")
+        .body(metrics)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Update system resource metrics
+pub fn update_system_metrics(state: &AppState) {
+    // Database connection metrics
+    let db_stats = state.db.size() as usize;
+    let db_idle = state.db.num_idle();
+    let redis_status = state.redis.status();
+    state.metrics.record_pools(
+        db_stats.saturating_sub(db_idle),
+        db_idle,
+        redis_status.size.saturating_sub(redis_status.available),
+    );
+
+    // Process memory, CPU, file descriptors and threads
+    match ProcessStats::read() {
+        Ok(stats) => state.metrics.record_process(stats),
+        Err(e) => debug!("Process stats unavailable: {:#}", e),
+    }
+
+    state.metrics.update_request_rate();
+}
+
+/// Start background metrics collection. Each collection runs inside this
+/// task, so it stops with it on shutdown; a slow one delays the next tick
+/// rather than overlapping it.
 pub async fn start_metrics_collection(state: AppState, config: MetricsConfig) {
     if !config.enabled {
         return;
@@ -283,16 +555,11 @@ pub async fn start_metrics_collection(state: AppState, config: MetricsConfig) {
 
     info!("Starting background metrics collection");
     let mut interval = interval(config.collection_interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
     loop {
         interval.tick().await;
-        
-        tokio::spawn({
-            let state = state.clone();
-            async move {
-                collect_system_metrics(&state).await;
-            }
-        });
+        collect_system_metrics(&state).await;
     }
 }
 
@@ -318,12 +585,12 @@ async fn collect_database_metrics(state: &AppState) {
         .await
     {
         Ok(count) => {
-            gauge!("active_users").set(count as f64);
-            record_database_query("count_active_users", start.elapsed(), true);
+            state.metrics.record_active_users(count);
+            state.metrics.record_database_query("count_active_users", start.elapsed(), true);
         }
         Err(e) => {
             warn!("Failed to collect user metrics: {}", e);
-            record_database_query("count_active_users", start.elapsed(), false);
+            state.metrics.record_database_query("count_active_users", start.elapsed(), false);
         }
     }
 }
@@ -335,34 +602,41 @@ async fn collect_redis_metrics(state: &AppState) {
         Ok(mut conn) => {
             match redis::cmd("INFO").query_async::<_, String>(&mut conn).await {
                 Ok(_) => {
-                    record_redis_command("info", start.elapsed(), true);
+                    state.metrics.record_redis_command("info", start.elapsed(), true);
                 }
                 Err(e) => {
                     warn!("Redis INFO command failed: {}", e);
-                    record_redis_command("info", start.elapsed(), false);
+                    state.metrics.record_redis_command("info", start.elapsed(), false);
                 }
             }
         }
         Err(e) => {
             warn!("Failed to get Redis connection for metrics: {}", e);
-            record_redis_command("connection", start.elapsed(), false);
+            state.metrics.record_redis_command("connection", start.elapsed(), false);
         }
     }
 }
 
 async fn update_business_metrics(state: &AppState) {
-    // Update API operations counter based on recent activity
-    // This would typically query your application-specific metrics
-    
-    // Example: Count API operations in the last minute
+    let (sample_rate, window) = {
+        let config = state.config.borrow();
+        (config.metrics.persistence_sample_rate, config.metrics.collection_interval)
+    };
+    if sample_rate <= 0.0 {
+        return;
+    }
+
+    // Count requests recorded since the last collection, scaled back up by
+    // the sample rate
     match sqlx::query_scalar::<_, i64>(
-        "SELECT COUNT(*) FROM performance_metrics WHERE timestamp > NOW() - INTERVAL '1 minute'"
+        "SELECT COUNT(*) FROM performance_metrics_partitioned WHERE timestamp > NOW() - $1::interval"
     )
+    .bind(format!("{} milliseconds", window.as_millis()))
     .fetch_one(&state.db)
     .await
     {
         Ok(count) => {
-            counter!("api_operations_total").increment(count as u64);
+            state.metrics.record_api_operations((count as f64 / sample_rate).round() as u64);
         }
         Err(e) => {
             warn!("Failed to collect API operation metrics: {}", e);
@@ -370,69 +644,96 @@ async fn update_business_metrics(state: &AppState) {
     }
 }
 
-/// Get current metrics summary
-pub async fn get_current_metrics() -> serde_json::Value {
-    unsafe {
-        if let Some(handle) = &PROMETHEUS_HANDLE {
-            let metrics_text = handle.render();
-            
-            // Parse key metrics from Prometheus format
-            let mut parsed_metrics = serde_json::Map::new();
-            
-            for line in metrics_text.lines() {
-                if line.starts_with("http_requests_total") {
-                    if let Some(value) = extract_metric_value(line) {
-                        parsed_metrics.insert("total_requests".to_string(), value.into());
-                    }
-                } else if line.starts_with("requests_per_second") {
-                    if let Some(value) = extract_metric_value(line) {
-                        parsed_metrics.insert("requests_per_second".to_string(), value.into());
-                    }
-                } else if line.starts_with("memory_usage_bytes") {
-                    if let Some(value) = extract_metric_value(line) {
-                        parsed_metrics.insert("memory_usage_bytes".to_string(), value.into());
-                    }
-                }
-            }
-            
-            serde_json::Value::Object(parsed_metrics)
-        } else {
-            serde_json::json!({
-                "error": "Metrics system not initialized"
-            })
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn registry(config: &MetricsConfig) -> Metrics {
+        Metrics::prometheus(config).unwrap()
     }
-}
 
-fn extract_metric_value(line: &str) -> Option<f64> {
-    line.split_whitespace()
-        .last()
-        .and_then(|s| s.parse().ok())
-}
+    /// The value of the first sample of `metric` whose labels contain `labels`
+    fn sample(rendered: &str, metric: &str, labels: &str) -> Option<f64> {
+        rendered
+            .lines()
+            .filter(|line| line.starts_with(metric) && line.contains(labels))
+            .find_map(|line| line.rsplit(' ').next()?.parse().ok())
+    }
 
-/// Middleware for automatic metrics collection
-pub struct MetricsMiddleware {
-    collector: Arc<PerformanceCollector>,
-}
+    #[test]
+    fn test_registries_are_isolated() {
+        let config = Config::default().metrics;
+        let first = registry(&config);
+        let second = registry(&config);
 
-impl MetricsMiddleware {
-    pub fn new() -> Self {
-        Self {
-            collector: Arc::new(PerformanceCollector::new()),
-        }
-    }
+        first.record_job_run("optimize_database", Duration::from_millis(5), true);
+        first.record_job_run("optimize_database", Duration::from_millis(5), true);
+        first.record_audit_events(3, 1);
+
+        let rendered = first.render().unwrap();
+        assert_eq!(sample(&rendered, "job_runs_total", "job=\"optimize_database\""), Some(2.0));
+        assert_eq!(sample(&rendered, "audit_events_dropped_total", ""), Some(1.0));
+        assert!(!second.render().unwrap().contains("job_runs_total{"));
 
-    pub fn record_request(&self) {
-        self.collector.record_request();
+        assert_eq!(Metrics::disabled().render(), None);
     }
 
-    pub fn get_current_rps(&self) -> f64 {
-        self.collector.calculate_rps()
+    #[test]
+    fn test_snapshot_counts_requests_and_errors() {
+        let metrics = registry(&Config::default().metrics);
+        metrics.record_http_request("GET", "/api/v1/users/:id", 200, Duration::from_millis(3));
+        metrics.record_http_request("GET", "/api/v1/users/:id", 404, Duration::from_millis(1));
+        metrics.record_http_request("POST", "/api/v1/users", 500, Duration::from_millis(9));
+        metrics.record_process(ProcessStats {
+            resident_memory_bytes: 1024,
+            ..Default::default()
+        });
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.total_requests, 3);
+        assert_eq!(snapshot.error_requests, 2);
+        assert_eq!(metrics.process().unwrap().resident_memory_bytes, 1024);
+        assert_eq!(
+            sample(&metrics.render().unwrap(), "process_resident_memory_bytes", ""),
+            Some(1024.0)
+        );
+
+        // Disabled metrics still keep the totals
+        let disabled = Metrics::disabled();
+        disabled.record_http_request("GET", "/health", 200, Duration::from_millis(1));
+        assert_eq!(disabled.snapshot().total_requests, 1);
     }
-}
 
-impl Default for MetricsMiddleware {
-    fn default() -> Self {
-        Self::new()
+    #[test]
+    fn test_route_labels_are_bounded() {
+        let mut config = Config::default().metrics;
+        config.route_labels = vec!["/api/v1/users/:id".to_string(), "/api/v1/users".to_string()];
+        config.max_route_labels = 1;
+        let metrics = registry(&config);
+
+        for route in ["/api/v1/users/:id", "/api/v1/users", "/graphql", UNMATCHED_ROUTE] {
+            metrics.record_http_request("GET", route, 200, Duration::from_millis(2));
+        }
+
+        let rendered = metrics.render().unwrap();
+        let requests = |path: &str| sample(&rendered, "http_requests_total", &format!("path=\"{}\"", path));
+        assert_eq!(requests("/api/v1/users/:id"), Some(1.0));
+        // Allowed, but past the label limit
+        assert_eq!(requests("/api/v1/users"), None);
+        assert_eq!(requests(OVERFLOW_ROUTE), Some(2.0));
+        assert_eq!(requests(UNMATCHED_ROUTE), Some(1.0));
+
+        // Extension methods share one label, matched route or not
+        for method in ["FOO1", "FOO2", "PROPFIND"] {
+            metrics.record_http_request(method, UNMATCHED_ROUTE, 405, Duration::from_millis(1));
+        }
+        let rendered = metrics.render().unwrap();
+        let label = format!("method=\"other\",path=\"{}\"", UNMATCHED_ROUTE);
+        assert_eq!(sample(&rendered, "http_requests_total", &label), Some(3.0));
+        assert!(!rendered.contains("FOO1") && !rendered.contains("PROPFIND"));
+
+        // Latency buckets come from the config, in seconds
+        assert!(rendered.contains("http_request_duration_seconds_bucket{") && rendered.contains("le=\"0.05\""));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,131 @@
+use chrono::{Days, NaiveDate, Utc};
+use tracing::warn;
+
+use crate::{config::MetricsConfig, database::DatabasePool};
+
+const PARTITIONED_TABLE: &str = "performance_metrics_partitioned";
+
+/// Daily partitions are named `performance_metrics_YYYY_MM_DD`, as migration
+/// 002 created them
+const PARTITION_PREFIX: &str = "performance_metrics_";
+const PARTITION_DATE_FORMAT: &str = "%Y_%m_%d";
+
+fn partition_name(day: NaiveDate) -> String {
+    format!("{}{}", PARTITION_PREFIX, day.format(PARTITION_DATE_FORMAT))
+}
+
+fn partition_day(name: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(name.strip_prefix(PARTITION_PREFIX)?, PARTITION_DATE_FORMAT).ok()
+}
+
+/// Partitions to create and to drop, given the existing ones
+fn plan(existing: &[String], today: NaiveDate, days_ahead: u32, retention_days: u32) -> (Vec<NaiveDate>, Vec<String>) {
+    let existing_days: Vec<_> = existing.iter().filter_map(|name| partition_day(name)).collect();
+    let create = (0..=u64::from(days_ahead))
+        .filter_map(|offset| today.checked_add_days(Days::new(offset)))
+        .filter(|day| !existing_days.contains(day))
+        .collect();
+
+    let oldest_kept = today - Days::new(u64::from(retention_days));
+    let drop = existing
+        .iter()
+        .filter(|name| partition_day(name).is_some_and(|day| day < oldest_kept))
+        .cloned()
+        .collect();
+
+    (create, drop)
+}
+
+/// Keeps daily partitions of `performance_metrics_partitioned` ready ahead
+/// of time and drops those past retention. Partition bounds are UTC days.
+#[derive(Clone)]
+pub struct PartitionManager {
+    db: DatabasePool,
+    days_ahead: u32,
+    retention_days: u32,
+}
+
+impl PartitionManager {
+    pub fn new(db: DatabasePool, config: &MetricsConfig) -> Self {
+        Self {
+            db,
+            days_ahead: config.partition_days_ahead,
+            retention_days: config.retention_days,
+        }
+    }
+
+    async fn existing(&self) -> anyhow::Result<Vec<String>> {
+        Ok(sqlx::query_scalar(
+            "SELECT c.relname::text FROM pg_inherits i
+             JOIN pg_class c ON c.oid = i.inhrelid
+             WHERE i.inhparent = $1::regclass",
+        )
+        .bind(PARTITIONED_TABLE)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    /// Create missing partitions and drop expired ones, returning how many of
+    /// each. A partition that fails is logged and retried on the next run.
+    pub async fn maintain(&self) -> anyhow::Result<(usize, usize)> {
+        let existing = self.existing().await?;
+        let (create, drop) = plan(&existing, Utc::now().date_naive(), self.days_ahead, self.retention_days);
+
+        let mut created = 0;
+        for day in create {
+            // Names and bounds come from dates, so they are safe to format in
+            let statement = format!(
+                "CREATE TABLE IF NOT EXISTS {} PARTITION OF {}
+                 FOR VALUES FROM ('{} 00:00:00+00') TO ('{} 00:00:00+00')",
+                partition_name(day),
+                PARTITIONED_TABLE,
+                day,
+                day + Days::new(1)
+            );
+            match sqlx::query(&statement).execute(&self.db).await {
+                Ok(_) => created += 1,
+                Err(e) => warn!("Failed to create partition {}: {}", partition_name(day), e),
+            }
+        }
+
+        let mut dropped = 0;
+        for name in drop {
+            match sqlx::query(&format!("DROP TABLE IF EXISTS {}", name)).execute(&self.db).await {
+                Ok(_) => dropped += 1,
+                Err(e) => warn!("Failed to drop expired partition {}: {}", name, e),
+            }
+        }
+
+        Ok((created, dropped))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_partition_names_round_trip() {
+        assert_eq!(partition_name(day("2024-06-01")), "performance_metrics_2024_06_01");
+        assert_eq!(partition_day("performance_metrics_2024_06_01"), Some(day("2024-06-01")));
+        assert_eq!(partition_day("performance_metrics_partitioned"), None);
+        assert_eq!(partition_day("audit_logs"), None);
+    }
+
+    #[test]
+    fn test_plan_creates_ahead_and_drops_expired() {
+        let existing: Vec<String> = ["2024-05-20", "2024-05-25", "2024-06-01", "2024-06-02"]
+            .into_iter()
+            .map(|d| partition_name(day(d)))
+            .chain(["performance_metrics_default".to_string()])
+            .collect();
+
+        let (create, drop) = plan(&existing, day("2024-06-01"), 3, 7);
+        assert_eq!(create, vec![day("2024-06-03"), day("2024-06-04")]);
+        assert_eq!(drop, vec![partition_name(day("2024-05-20"))]);
+    }
+}
@@ -0,0 +1,236 @@
+use anyhow::Context;
+use axum::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use sqlx::postgres::PgPoolCopyExt;
+use std::{
+    cell::Cell,
+    collections::hash_map::RandomState,
+    fmt::Write,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+use super::Metrics;
+use crate::{
+    batch::{BatchSink, BatchWriter},
+    config::MetricsConfig,
+    database::DatabasePool,
+};
+
+/// Column widths in `performance_metrics`
+const MAX_ENDPOINT_LEN: usize = 255;
+const MAX_METHOD_LEN: usize = 10;
+
+/// Timing of one request, as stored in `performance_metrics_partitioned`
+#[derive(Debug, Clone)]
+pub struct RequestRecord {
+    pub endpoint: String,
+    pub method: String,
+    pub response_time_ms: i32,
+    pub status_code: u16,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl RequestRecord {
+    pub fn new(endpoint: &str, method: &str, status_code: u16, duration: Duration) -> Self {
+        Self {
+            endpoint: endpoint.chars().take(MAX_ENDPOINT_LEN).collect(),
+            method: method.chars().take(MAX_METHOD_LEN).collect(),
+            response_time_ms: duration.as_millis().try_into().unwrap_or(i32::MAX),
+            status_code,
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+/// Whether to keep one request when keeping `rate` of them. Uses a
+/// per-thread xorshift generator, which is plenty for sampling and costs
+/// nothing on the request path.
+fn sampled(rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    if rate <= 0.0 {
+        return false;
+    }
+
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new({
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u64(0);
+            hasher.finish() | 1
+        });
+    }
+
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        ((x >> 11) as f64 / (1u64 << 53) as f64) < rate
+    })
+}
+
+/// Append `value` as a CSV field, quoting it when needed
+fn push_csv_field(line: &mut String, value: &str) {
+    if value.contains([',', '"', '\n', '\r']) {
+        line.push('"');
+        line.push_str(&value.replace('"', "\"\""));
+        line.push('"');
+    } else {
+        line.push_str(value);
+    }
+}
+
+fn push_csv_row(buffer: &mut String, record: &RequestRecord) {
+    push_csv_field(buffer, &record.endpoint);
+    buffer.push(',');
+    push_csv_field(buffer, &record.method);
+    let _ = writeln!(
+        buffer,
+        ",{},{},{}",
+        record.response_time_ms,
+        record.status_code,
+        record.timestamp.to_rfc3339_opts(SecondsFormat::Micros, true)
+    );
+}
+
+/// Samples request timings and hands them to a [`RequestRecordWriter`]
+/// without blocking. Records that don't fit in the queue are dropped and
+/// counted in `request_records_dropped_total`.
+#[derive(Clone)]
+pub struct RequestRecorder {
+    records: Option<mpsc::Sender<RequestRecord>>,
+    sample_rate: f64,
+    metrics: Metrics,
+}
+
+impl RequestRecorder {
+    /// The recorder and the writer that drains it, which the caller must
+    /// spawn. The writer is `None` when the sample rate is zero.
+    pub fn new(
+        db: DatabasePool,
+        config: &MetricsConfig,
+        metrics: Metrics,
+    ) -> (Self, Option<RequestRecordWriter>) {
+        if config.persistence_sample_rate <= 0.0 {
+            return (Self::disabled(), None);
+        }
+
+        let (records, receiver) = mpsc::channel(config.persistence_channel_capacity);
+        let sink = RequestRecordSink {
+            db,
+            metrics: metrics.clone(),
+        };
+        let writer = BatchWriter::new(
+            sink,
+            receiver,
+            config.persistence_batch_size,
+            config.persistence_flush_interval,
+        );
+        let recorder = Self {
+            records: Some(records),
+            sample_rate: config.persistence_sample_rate,
+            metrics,
+        };
+        (recorder, Some(writer))
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            records: None,
+            sample_rate: 0.0,
+            metrics: Metrics::disabled(),
+        }
+    }
+
+    /// Whether the current request should be recorded; check this before
+    /// building a [`RequestRecord`] so unsampled requests cost nothing
+    pub fn sample(&self) -> bool {
+        self.records.is_some() && sampled(self.sample_rate)
+    }
+
+    pub fn record(&self, record: RequestRecord) {
+        let Some(records) = &self.records else {
+            return;
+        };
+
+        if records.try_send(record).is_err() {
+            self.metrics.record_request_records(0, 1);
+        }
+    }
+}
+
+/// Drains queued request records into `performance_metrics_partitioned`
+pub type RequestRecordWriter = BatchWriter<RequestRecordSink>;
+
+/// Copies request records into `performance_metrics_partitioned`, one
+/// `COPY` per batch
+pub struct RequestRecordSink {
+    db: DatabasePool,
+    metrics: Metrics,
+}
+
+#[async_trait]
+impl BatchSink for RequestRecordSink {
+    type Item = RequestRecord;
+
+    fn record(&self, written: u64, dropped: u64) {
+        self.metrics.record_request_records(written, dropped);
+    }
+
+    async fn write(&self, batch: &[RequestRecord]) -> anyhow::Result<()> {
+        let mut buffer = String::with_capacity(batch.len() * 96);
+        for record in batch {
+            push_csv_row(&mut buffer, record);
+        }
+
+        let mut copy = self
+            .db
+            .copy_in_raw(
+                "COPY performance_metrics_partitioned (endpoint, method, response_time_ms, status_code, timestamp)
+                 FROM STDIN WITH (FORMAT csv)",
+            )
+            .await
+            .context("failed to start copying request records")?;
+
+        if let Err(e) = copy.send(buffer.into_bytes()).await {
+            let _ = copy.abort(e.to_string()).await;
+            return Err(e).context("failed to copy request records");
+        }
+
+        copy.finish()
+            .await
+            .with_context(|| format!("failed to write {} request records", batch.len()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_rows_quote_only_when_needed() {
+        let mut record = RequestRecord::new("/api/v1/users/:id", "GET", 200, Duration::from_micros(12_500));
+        record.timestamp = "2024-06-01T12:00:00.000001Z".parse().unwrap();
+        let mut buffer = String::new();
+        push_csv_row(&mut buffer, &record);
+        assert_eq!(buffer, "/api/v1/users/:id,GET,12,200,2024-06-01T12:00:00.000001Z\n");
+
+        let mut buffer = String::new();
+        push_csv_field(&mut buffer, "/search,\"x\"");
+        assert_eq!(buffer, "\"/search,\"\"x\"\"\"");
+    }
+
+    #[test]
+    fn test_sampling_bounds() {
+        assert!((0..1000).all(|_| sampled(1.0)));
+        assert!((0..1000).all(|_| !sampled(0.0)));
+
+        let kept = (0..100_000).filter(|_| sampled(0.1)).count();
+        assert!((8_000..12_000).contains(&kept), "kept {}", kept);
+    }
+}
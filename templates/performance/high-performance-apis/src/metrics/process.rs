@@ -0,0 +1,132 @@
+use anyhow::Context;
+use serde::Serialize;
+
+/// Clock ticks per second in `/proc/self/stat`. USER_HZ is 100 on every
+/// architecture Linux ships, which saves a libc dependency for `sysconf`.
+const CLOCK_TICKS_PER_SECOND: f64 = 100.0;
+
+/// Resource usage of this process, read from procfs and the cgroup v2
+/// hierarchy. Only available on Linux.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ProcessStats {
+    pub resident_memory_bytes: u64,
+    /// User and system CPU time since the process started
+    pub cpu_seconds: f64,
+    pub open_fds: u64,
+    pub threads: u64,
+    /// The container's `memory.max`, if it sets one
+    pub memory_limit_bytes: Option<u64>,
+}
+
+impl ProcessStats {
+    #[cfg(target_os = "linux")]
+    pub fn read() -> anyhow::Result<Self> {
+        use std::fs;
+
+        let stat = fs::read_to_string("/proc/self/stat").context("failed to read /proc/self/stat")?;
+        let status = fs::read_to_string("/proc/self/status").context("failed to read /proc/self/status")?;
+        let open_fds = fs::read_dir("/proc/self/fd")
+            .context("failed to list /proc/self/fd")?
+            .count() as u64;
+
+        let mut stats = Self {
+            cpu_seconds: parse_cpu_seconds(&stat)?,
+            open_fds,
+            memory_limit_bytes: read_memory_limit(),
+            ..Default::default()
+        };
+        parse_status(&status, &mut stats)?;
+        Ok(stats)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn read() -> anyhow::Result<Self> {
+        anyhow::bail!("process stats are only available on Linux")
+    }
+}
+
+/// `utime + stime` from `/proc/self/stat`. The command name can hold spaces
+/// and parentheses, so fields are counted from the last `)`.
+fn parse_cpu_seconds(stat: &str) -> anyhow::Result<f64> {
+    let fields: Vec<&str> = stat
+        .rsplit_once(')')
+        .map(|(_, rest)| rest.split_whitespace().collect())
+        .unwrap_or_default();
+
+    // utime and stime are fields 14 and 15; `fields` starts at field 3
+    let ticks = |index: usize| -> anyhow::Result<u64> {
+        fields
+            .get(index)
+            .and_then(|field| field.parse().ok())
+            .with_context(|| format!("malformed /proc/self/stat field {}", index + 3))
+    };
+    Ok((ticks(11)? + ticks(12)?) as f64 / CLOCK_TICKS_PER_SECOND)
+}
+
+/// Resident memory (`VmRSS`, in kB) and thread count from `/proc/self/status`
+fn parse_status(status: &str, stats: &mut ProcessStats) -> anyhow::Result<()> {
+    let field = |name: &str| -> anyhow::Result<u64> {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|value| value.split_whitespace().next()?.parse().ok())
+            .with_context(|| format!("no {} in /proc/self/status", name))
+    };
+
+    stats.resident_memory_bytes = field("VmRSS")? * 1024;
+    stats.threads = field("Threads")?;
+    Ok(())
+}
+
+/// The cgroup v2 path from `/proc/self/cgroup`, e.g. `/` inside most
+/// containers
+fn parse_cgroup_path(cgroup: &str) -> Option<&str> {
+    cgroup.lines().find_map(|line| line.strip_prefix("0::"))
+}
+
+/// A cgroup `memory.max`; `max` means no limit
+fn parse_memory_max(value: &str) -> Option<u64> {
+    value.trim().parse().ok()
+}
+
+#[cfg(target_os = "linux")]
+fn read_memory_limit() -> Option<u64> {
+    let cgroup = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+    let path = parse_cgroup_path(&cgroup)?.trim_end_matches('/');
+    let limit = std::fs::read_to_string(format!("/sys/fs/cgroup{}/memory.max", path))
+        .or_else(|_| std::fs::read_to_string("/sys/fs/cgroup/memory.max"))
+        .ok()?;
+    parse_memory_max(&limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stat_and_status() {
+        let stat = "4242 (my (api) server) S 1 4242 4242 0 -1 4194560 2593 0 0 0 \
+                    250 75 0 0 20 0 9 0 1234567 123456789 3000 18446744073709551615";
+        assert_eq!(parse_cpu_seconds(stat).unwrap(), 3.25);
+        assert!(parse_cpu_seconds("4242 (truncated) S 1").is_err());
+
+        let status = "Name:\tserver\nVmPeak:\t  204800 kB\nVmRSS:\t   51200 kB\nThreads:\t9\n";
+        let mut stats = ProcessStats::default();
+        parse_status(status, &mut stats).unwrap();
+        assert_eq!(stats.resident_memory_bytes, 51200 * 1024);
+        assert_eq!(stats.threads, 9);
+    }
+
+    #[test]
+    fn test_parse_cgroup_memory_limit() {
+        assert_eq!(parse_cgroup_path("0::/\n"), Some("/"));
+        assert_eq!(
+            parse_cgroup_path("1:name=systemd:/init.scope\n0::/kubepods/pod1/api\n"),
+            Some("/kubepods/pod1/api")
+        );
+        assert_eq!(parse_cgroup_path("4:memory:/docker/abc\n"), None);
+
+        assert_eq!(parse_memory_max("536870912\n"), Some(536_870_912));
+        assert_eq!(parse_memory_max("max\n"), None);
+    }
+}
@@ -0,0 +1,72 @@
+use axum::{extract::Request, response::Response};
+use tower::{Layer, Service};
+
+use crate::{
+    audit::{actions, AuditEvent, AuditLog, AuditSource},
+    auth::Principal,
+};
+
+/// Records every request it wraps, with the caller, the route and the
+/// response status. Must run inside `AuthLayer` so the caller is known.
+#[derive(Clone)]
+pub struct AuditLayer {
+    audit: AuditLog,
+}
+
+impl AuditLayer {
+    pub fn new(audit: AuditLog) -> Self {
+        Self { audit }
+    }
+}
+
+impl<S> Layer<S> for AuditLayer {
+    type Service = AuditService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuditService {
+            inner,
+            audit: self.audit.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AuditService<S> {
+    inner: S,
+    audit: AuditLog,
+}
+
+impl<S> Service<Request> for AuditService<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = std::pin::Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let mut event = AuditEvent::new(actions::ADMIN_REQUEST)
+            .request(&request)
+            .source(&AuditSource::from_parts(request.extensions(), request.headers()));
+        if let Some(principal) = request.extensions().get::<Principal>() {
+            event = event.actor(principal);
+        }
+
+        let audit = self.audit.clone();
+        let future = self.inner.call(request);
+
+        Box::pin(async move {
+            let response = future.await?;
+            audit.record(event.detail("status", response.status().as_u16()));
+            Ok(response)
+        })
+    }
+}
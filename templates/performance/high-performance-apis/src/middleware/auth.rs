@@ -0,0 +1,90 @@
+use axum::{
+    extract::Request,
+    response::{IntoResponse, Response},
+};
+use tower::{Layer, Service};
+
+use crate::{
+    audit::{actions, AuditEvent, AuditLog, AuditSource},
+    auth::Authenticator,
+    client_ip::ClientIp,
+    server::tls::ClientCertificate,
+};
+
+/// Requires a valid JWT, API key or client certificate and adds the resulting
+/// [`Principal`](crate::auth::Principal) to the request extensions. Rejected
+/// requests are recorded in the audit log.
+#[derive(Clone)]
+pub struct AuthLayer {
+    authenticator: Authenticator,
+    audit: AuditLog,
+}
+
+impl AuthLayer {
+    pub fn new(authenticator: Authenticator, audit: AuditLog) -> Self {
+        Self { authenticator, audit }
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = AuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthService {
+            inner,
+            authenticator: self.authenticator.clone(),
+            audit: self.audit.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AuthService<S> {
+    inner: S,
+    authenticator: Authenticator,
+    audit: AuditLog,
+}
+
+impl<S> Service<Request> for AuthService<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = std::pin::Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        let authenticator = self.authenticator.clone();
+        let audit = self.audit.clone();
+        let mut inner = self.inner.clone();
+
+        Box::pin(async move {
+            let certificate = request.extensions().get::<ClientCertificate>();
+            let client_ip = request.extensions().get::<ClientIp>().map(|ClientIp(ip)| *ip);
+            match authenticator.authenticate(request.headers(), certificate, client_ip).await {
+                Ok(principal) => {
+                    request.extensions_mut().insert(principal);
+                    inner.call(request).await
+                }
+                Err(e) => {
+                    tracing::debug!("Rejected request credentials: {}", e);
+                    audit.record(
+                        AuditEvent::new(actions::AUTH_FAILURE)
+                            .detail("reason", e.to_string())
+                            .request(&request)
+                            .source(&AuditSource::from_parts(request.extensions(), request.headers())),
+                    );
+                    Ok(e.into_response())
+                }
+            }
+        })
+    }
+}
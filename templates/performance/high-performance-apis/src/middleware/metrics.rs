@@ -0,0 +1,89 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    response::Response,
+};
+use std::time::Instant;
+use tower::{Layer, Service};
+
+use crate::metrics::{
+    persistence::{RequestRecord, RequestRecorder},
+    Metrics, UNMATCHED_ROUTE,
+};
+
+/// Records request counts and latencies, and hands sampled requests to the
+/// [`RequestRecorder`]. Requests are keyed by their route template, e.g.
+/// `/api/v1/users/:id`, so ids in paths don't each become an endpoint.
+#[derive(Clone)]
+pub struct MetricsLayer {
+    metrics: Metrics,
+    requests: RequestRecorder,
+}
+
+impl MetricsLayer {
+    pub fn new(metrics: Metrics, requests: RequestRecorder) -> Self {
+        Self { metrics, requests }
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsService {
+            inner,
+            metrics: self.metrics.clone(),
+            requests: self.requests.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MetricsService<S> {
+    inner: S,
+    metrics: Metrics,
+    requests: RequestRecorder,
+}
+
+impl<S> Service<Request> for MetricsService<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = std::pin::Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let start = Instant::now();
+        let method = request.method().clone();
+        let endpoint = request
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|path| path.as_str().to_string())
+            .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+
+        let metrics = self.metrics.clone();
+        let requests = self.requests.clone();
+        let future = self.inner.call(request);
+
+        Box::pin(async move {
+            let response = future.await?;
+            let duration = start.elapsed();
+            let status = response.status().as_u16();
+
+            metrics.record_http_request(method.as_str(), &endpoint, status, duration);
+            if requests.sample() {
+                requests.record(RequestRecord::new(&endpoint, method.as_str(), status, duration));
+            }
+
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,4 @@
+pub mod audit;
+pub mod auth;
+pub mod metrics;
+pub mod scope;
@@ -0,0 +1,83 @@
+use axum::{
+    extract::Request,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+use tower::{Layer, Service};
+
+use crate::auth::{scopes::check_scopes, AuthError, Principal};
+
+/// Rejects requests whose [`Principal`] lacks any of the given scopes with a
+/// `403` problem response. Must run inside `AuthLayer`; attach it with
+/// `route_layer` to a router, or `layer` to a single route:
+///
+/// ```ignore
+/// Router::new()
+///     .route("/stats", get(admin_stats).layer(RequireScopeLayer::new(["admin:read"])))
+///     .layer(AuthLayer::new(auth, audit))
+/// ```
+#[derive(Clone)]
+pub struct RequireScopeLayer {
+    scopes: Arc<[String]>,
+}
+
+impl RequireScopeLayer {
+    pub fn new<I, S>(scopes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            scopes: scopes.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl<S> Layer<S> for RequireScopeLayer {
+    type Service = RequireScopeService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequireScopeService {
+            inner,
+            scopes: self.scopes.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequireScopeService<S> {
+    inner: S,
+    scopes: Arc<[String]>,
+}
+
+impl<S> Service<Request> for RequireScopeService<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = std::pin::Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let checked = match request.extensions().get::<Principal>() {
+            Some(principal) => check_scopes(principal, &self.scopes).map_err(IntoResponse::into_response),
+            None => Err(AuthError::MissingToken.into_response()),
+        };
+
+        match checked {
+            Ok(()) => {
+                let future = self.inner.call(request);
+                Box::pin(future)
+            }
+            Err(response) => Box::pin(async move { Ok(response) }),
+        }
+    }
+}
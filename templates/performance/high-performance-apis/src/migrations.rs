@@ -0,0 +1,755 @@
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use sqlx::{pool::PoolConnection, Executor, Postgres};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use tracing::{info, warn};
+
+use crate::{cli::MigrateCommand, database::DatabasePool};
+
+/// Advisory lock key held while migrations run so replicas starting at the
+/// same time don't race each other ("migr" in ASCII)
+const MIGRATION_LOCK_KEY: i64 = 0x6d69_6772;
+
+/// Header directive that makes a migration run outside a transaction
+const NO_TRANSACTION_DIRECTIVE: &str = "-- migrate:no-transaction";
+
+/// Header directive carrying the human-readable description
+const DESCRIPTION_DIRECTIVE: &str = "-- description:";
+
+/// A single versioned migration loaded from the migrations directory
+#[derive(Debug, Clone)]
+pub struct Migration {
+    /// Full version identifier, e.g. `001_initial_schema`
+    pub version: String,
+    /// Numeric prefix used for ordering
+    pub sequence: u64,
+    pub description: String,
+    pub up_sql: String,
+    pub down_sql: Option<String>,
+    /// SHA-256 of the up migration, used to detect edits to applied files
+    pub checksum: String,
+    /// Whether the migration runs inside a transaction
+    pub transactional: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// Applied, but the file on disk no longer matches the recorded checksum
+    Modified,
+    /// Recorded in `_migrations` but the file is gone
+    Missing,
+}
+
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: String,
+    pub description: String,
+    pub state: MigrationState,
+    pub executed_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Clone)]
+struct AppliedMigration {
+    checksum: Option<String>,
+    executed_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// File-based migration runner backed by the `_migrations` table
+#[derive(Debug, Clone)]
+pub struct Migrator {
+    migrations: Vec<Migration>,
+}
+
+impl Migrator {
+    /// Load `NNN_name.up.sql` / `NNN_name.down.sql` pairs from a directory
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let entries = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read migrations directory {}", dir.display()))?;
+
+        let mut up_files: HashMap<String, PathBuf> = HashMap::new();
+        let mut down_files: HashMap<String, PathBuf> = HashMap::new();
+
+        for entry in entries {
+            let path = entry?.path();
+            let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+
+            if let Some(version) = file_name.strip_suffix(".up.sql") {
+                up_files.insert(version.to_string(), path.clone());
+            } else if let Some(version) = file_name.strip_suffix(".down.sql") {
+                down_files.insert(version.to_string(), path.clone());
+            }
+        }
+
+        let mut migrations = Vec::with_capacity(up_files.len());
+        for (version, up_path) in up_files {
+            let up_sql = std::fs::read_to_string(&up_path)
+                .with_context(|| format!("Failed to read {}", up_path.display()))?;
+            let down_sql = match down_files.remove(&version) {
+                Some(path) => Some(
+                    std::fs::read_to_string(&path)
+                        .with_context(|| format!("Failed to read {}", path.display()))?,
+                ),
+                None => None,
+            };
+
+            migrations.push(Migration::new(&version, up_sql, down_sql)?);
+        }
+
+        if let Some(orphan) = down_files.keys().next() {
+            bail!("Down migration {} has no matching up migration", orphan);
+        }
+
+        Self::from_migrations(migrations)
+    }
+
+    /// Build a migrator from already-loaded migrations
+    pub fn from_migrations(mut migrations: Vec<Migration>) -> Result<Self> {
+        migrations.sort_by_key(|m| m.sequence);
+
+        for pair in migrations.windows(2) {
+            if pair[0].sequence == pair[1].sequence {
+                bail!(
+                    "Migrations {} and {} share sequence number {}",
+                    pair[0].version,
+                    pair[1].version,
+                    pair[0].sequence
+                );
+            }
+        }
+
+        Ok(Self { migrations })
+    }
+
+    pub fn migrations(&self) -> &[Migration] {
+        &self.migrations
+    }
+
+    /// Report the state of every known and recorded migration
+    pub async fn status(&self, pool: &DatabasePool) -> Result<Vec<MigrationStatus>> {
+        let mut conn = pool.acquire().await?;
+        ensure_migrations_table(&mut conn).await?;
+        let applied = fetch_applied(&mut conn).await?;
+
+        let mut statuses: Vec<MigrationStatus> = self
+            .migrations
+            .iter()
+            .map(|migration| {
+                let (state, executed_at) = match applied.get(&migration.version) {
+                    Some(record) => {
+                        let state = match &record.checksum {
+                            Some(checksum) if *checksum != migration.checksum => {
+                                MigrationState::Modified
+                            }
+                            _ => MigrationState::Applied,
+                        };
+                        (state, record.executed_at)
+                    }
+                    None => (MigrationState::Pending, None),
+                };
+
+                MigrationStatus {
+                    version: migration.version.clone(),
+                    description: migration.description.clone(),
+                    state,
+                    executed_at,
+                }
+            })
+            .collect();
+
+        for (version, record) in &applied {
+            if !self.migrations.iter().any(|m| &m.version == version) {
+                statuses.push(MigrationStatus {
+                    version: version.clone(),
+                    description: String::new(),
+                    state: MigrationState::Missing,
+                    executed_at: record.executed_at,
+                });
+            }
+        }
+
+        statuses.sort_by(|a, b| a.version.cmp(&b.version));
+        Ok(statuses)
+    }
+
+    /// Apply pending migrations in order, optionally stopping at `target`
+    pub async fn up(&self, pool: &DatabasePool, target: Option<&str>) -> Result<usize> {
+        if let Some(target) = target {
+            if !self.migrations.iter().any(|m| m.version == target) {
+                bail!("Unknown migration target {}", target);
+            }
+        }
+
+        let mut conn = lock(pool).await?;
+        let result = self.up_locked(&mut conn, target).await;
+        unlock(&mut conn).await;
+        result
+    }
+
+    /// Revert the most recently applied `steps` migrations
+    pub async fn down(&self, pool: &DatabasePool, steps: usize) -> Result<usize> {
+        let mut conn = lock(pool).await?;
+        let result = self.down_locked(&mut conn, steps).await;
+        unlock(&mut conn).await;
+        result.map(|reverted| reverted.len())
+    }
+
+    /// Revert and re-apply the most recently applied `steps` migrations
+    pub async fn redo(&self, pool: &DatabasePool, steps: usize) -> Result<usize> {
+        let mut conn = lock(pool).await?;
+        let result = async {
+            let reverted = self.down_locked(&mut conn, steps).await?;
+            // Only re-apply what was reverted, not unrelated pending migrations
+            if let Some(newest) = reverted.first() {
+                self.up_locked(&mut conn, Some(newest.as_str())).await?;
+            }
+            Ok(reverted.len())
+        }
+        .await;
+        unlock(&mut conn).await;
+        result
+    }
+
+    async fn up_locked(
+        &self,
+        conn: &mut PoolConnection<Postgres>,
+        target: Option<&str>,
+    ) -> Result<usize> {
+        let applied = self.verify_applied(conn).await?;
+        let mut count = 0;
+
+        for migration in &self.migrations {
+            if !applied.contains_key(&migration.version) {
+                apply(conn, migration).await?;
+                count += 1;
+            }
+
+            if target == Some(migration.version.as_str()) {
+                break;
+            }
+        }
+
+        if count == 0 {
+            info!("Database schema is up to date");
+        }
+
+        Ok(count)
+    }
+
+    /// Returns the reverted versions, newest first
+    async fn down_locked(
+        &self,
+        conn: &mut PoolConnection<Postgres>,
+        steps: usize,
+    ) -> Result<Vec<String>> {
+        let applied = self.verify_applied(conn).await?;
+        let to_revert: Vec<&Migration> = self
+            .migrations
+            .iter()
+            .rev()
+            .filter(|m| applied.contains_key(&m.version))
+            .take(steps)
+            .collect();
+
+        let mut reverted = Vec::with_capacity(to_revert.len());
+        for migration in to_revert {
+            revert(conn, migration).await?;
+            reverted.push(migration.version.clone());
+        }
+
+        Ok(reverted)
+    }
+
+    /// Refuse to continue if any applied migration was edited after it ran.
+    /// Rows written before checksums were tracked adopt the current file.
+    async fn verify_applied(
+        &self,
+        conn: &mut PoolConnection<Postgres>,
+    ) -> Result<HashMap<String, AppliedMigration>> {
+        ensure_migrations_table(conn).await?;
+        let applied = fetch_applied(conn).await?;
+
+        for (version, record) in &applied {
+            let Some(migration) = self.migrations.iter().find(|m| &m.version == version) else {
+                warn!("Applied migration {} has no file on disk", version);
+                continue;
+            };
+
+            match &record.checksum {
+                Some(checksum) if *checksum != migration.checksum => {
+                    bail!(
+                        "Migration {} was modified after it was applied (recorded checksum {}, file checksum {}); \
+                         add a new migration instead of editing an applied one",
+                        version,
+                        checksum,
+                        migration.checksum
+                    );
+                }
+                Some(_) => {}
+                None => {
+                    info!("Recording checksum for previously applied migration {}", version);
+                    sqlx::query("UPDATE _migrations SET checksum = $1 WHERE version = $2")
+                        .bind(&migration.checksum)
+                        .bind(version)
+                        .execute(&mut **conn)
+                        .await?;
+                }
+            }
+        }
+
+        Ok(applied)
+    }
+}
+
+impl Migration {
+    /// Build a migration from its version identifier and SQL sources
+    pub fn new(version: &str, up_sql: String, down_sql: Option<String>) -> Result<Self> {
+        let sequence = version
+            .split('_')
+            .next()
+            .and_then(|prefix| prefix.parse::<u64>().ok())
+            .with_context(|| {
+                format!("Migration {} must start with a numeric prefix, e.g. 003_add_table", version)
+            })?;
+
+        let header = header_lines(&up_sql);
+        let transactional = !header.iter().any(|line| line.trim() == NO_TRANSACTION_DIRECTIVE);
+        let description = header
+            .iter()
+            .find_map(|line| line.trim().strip_prefix(DESCRIPTION_DIRECTIVE))
+            .map(|d| d.trim().to_string())
+            .unwrap_or_else(|| {
+                version
+                    .split_once('_')
+                    .map_or(version, |(_, name)| name)
+                    .replace('_', " ")
+            });
+
+        Ok(Self {
+            version: version.to_string(),
+            sequence,
+            description,
+            checksum: checksum(&up_sql),
+            up_sql,
+            down_sql,
+            transactional,
+        })
+    }
+}
+
+/// Leading comment block of a migration file, where directives live
+fn header_lines(sql: &str) -> Vec<&str> {
+    sql.lines()
+        .take_while(|line| line.trim().is_empty() || line.trim_start().starts_with("--"))
+        .collect()
+}
+
+/// Checksum of a migration, normalising line endings so checkouts with
+/// `core.autocrlf` don't look like edits
+pub fn checksum(sql: &str) -> String {
+    let normalized = sql.replace("\r\n", "\n");
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+async fn ensure_migrations_table(conn: &mut PoolConnection<Postgres>) -> Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS _migrations (
+            id SERIAL PRIMARY KEY,
+            version VARCHAR(255) NOT NULL UNIQUE,
+            description TEXT,
+            executed_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+        )
+        "#,
+    )
+    .execute(&mut **conn)
+    .await?;
+
+    sqlx::query("ALTER TABLE _migrations ADD COLUMN IF NOT EXISTS checksum VARCHAR(64)")
+        .execute(&mut **conn)
+        .await?;
+
+    sqlx::query("ALTER TABLE _migrations ADD COLUMN IF NOT EXISTS execution_time_ms BIGINT")
+        .execute(&mut **conn)
+        .await?;
+
+    Ok(())
+}
+
+async fn fetch_applied(
+    conn: &mut PoolConnection<Postgres>,
+) -> Result<HashMap<String, AppliedMigration>> {
+    let rows = sqlx::query_as::<_, (String, Option<String>, Option<chrono::DateTime<chrono::Utc>>)>(
+        "SELECT version, checksum, executed_at FROM _migrations",
+    )
+    .fetch_all(&mut **conn)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(version, checksum, executed_at)| {
+            (version, AppliedMigration { checksum, executed_at })
+        })
+        .collect())
+}
+
+async fn lock(pool: &DatabasePool) -> Result<PoolConnection<Postgres>> {
+    let mut conn = pool.acquire().await?;
+    sqlx::query("SELECT pg_advisory_lock($1)")
+        .bind(MIGRATION_LOCK_KEY)
+        .execute(&mut *conn)
+        .await?;
+    Ok(conn)
+}
+
+async fn unlock(conn: &mut PoolConnection<Postgres>) {
+    if let Err(e) = sqlx::query("SELECT pg_advisory_unlock($1)")
+        .bind(MIGRATION_LOCK_KEY)
+        .execute(&mut **conn)
+        .await
+    {
+        warn!("Failed to release migration lock: {}", e);
+    }
+}
+
+async fn apply(conn: &mut PoolConnection<Postgres>, migration: &Migration) -> Result<()> {
+    info!("Running migration {}: {}", migration.version, migration.description);
+    let start = Instant::now();
+
+    if migration.transactional {
+        let mut tx = sqlx::Connection::begin(&mut **conn).await?;
+        (&mut *tx)
+            .execute(migration.up_sql.as_str())
+            .await
+            .with_context(|| format!("Migration {} failed", migration.version))?;
+        record(&mut *tx, migration, start).await?;
+        tx.commit().await?;
+    } else {
+        execute_statements(conn, &migration.up_sql)
+            .await
+            .with_context(|| format!("Migration {} failed", migration.version))?;
+        record(&mut **conn, migration, start).await?;
+    }
+
+    info!(
+        "Migration {} completed in {}ms",
+        migration.version,
+        start.elapsed().as_millis()
+    );
+    Ok(())
+}
+
+async fn revert(conn: &mut PoolConnection<Postgres>, migration: &Migration) -> Result<()> {
+    let Some(down_sql) = &migration.down_sql else {
+        bail!("Migration {} has no down migration", migration.version);
+    };
+
+    info!("Reverting migration {}: {}", migration.version, migration.description);
+
+    if migration.transactional {
+        let mut tx = sqlx::Connection::begin(&mut **conn).await?;
+        (&mut *tx)
+            .execute(down_sql.as_str())
+            .await
+            .with_context(|| format!("Reverting migration {} failed", migration.version))?;
+        forget(&mut *tx, migration).await?;
+        tx.commit().await?;
+    } else {
+        execute_statements(conn, down_sql)
+            .await
+            .with_context(|| format!("Reverting migration {} failed", migration.version))?;
+        forget(&mut **conn, migration).await?;
+    }
+
+    Ok(())
+}
+
+/// Run a script one statement at a time. Each statement gets its own implicit
+/// transaction, which is what `CREATE INDEX CONCURRENTLY` and friends need.
+async fn execute_statements(conn: &mut PoolConnection<Postgres>, sql: &str) -> Result<()> {
+    for statement in split_statements(sql) {
+        if let Err(e) = (&mut **conn).execute(statement.as_str()).await {
+            warn!("Error executing migration statement: {}", e);
+            warn!("Statement: {}", statement);
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+async fn record(
+    conn: &mut sqlx::PgConnection,
+    migration: &Migration,
+    start: Instant,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO _migrations (version, description, checksum, execution_time_ms) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(&migration.version)
+    .bind(&migration.description)
+    .bind(&migration.checksum)
+    .bind(start.elapsed().as_millis() as i64)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+async fn forget(conn: &mut sqlx::PgConnection, migration: &Migration) -> Result<()> {
+    sqlx::query("DELETE FROM _migrations WHERE version = $1")
+        .bind(&migration.version)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Split a SQL script into statements on top-level `;`, respecting quoted
+/// strings, identifiers, comments and dollar-quoted bodies (`DO $$ ... $$`,
+/// plpgsql functions). Comment-only fragments are dropped.
+pub fn split_statements(sql: &str) -> Vec<String> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut has_code = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        // Line comment
+        if c == '-' && chars.get(i + 1) == Some(&'-') {
+            while i < chars.len() && chars[i] != '\n' {
+                current.push(chars[i]);
+                i += 1;
+            }
+            continue;
+        }
+
+        // Block comment (PostgreSQL allows nesting)
+        if c == '/' && chars.get(i + 1) == Some(&'*') {
+            let mut depth = 0;
+            while i < chars.len() {
+                if chars[i] == '/' && chars.get(i + 1) == Some(&'*') {
+                    depth += 1;
+                    current.push_str("/*");
+                    i += 2;
+                } else if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                    depth -= 1;
+                    current.push_str("*/");
+                    i += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    current.push(chars[i]);
+                    i += 1;
+                }
+            }
+            continue;
+        }
+
+        // Quoted string or identifier; doubled quotes re-open on the next pass
+        if c == '\'' || c == '"' {
+            has_code = true;
+            current.push(c);
+            i += 1;
+            while i < chars.len() {
+                current.push(chars[i]);
+                i += 1;
+                if chars[i - 1] == c {
+                    break;
+                }
+            }
+            continue;
+        }
+
+        // Dollar-quoted body
+        if c == '$' {
+            if let Some(tag) = dollar_tag(&chars, i) {
+                has_code = true;
+                current.push_str(&tag);
+                i += tag.chars().count();
+
+                let tag_chars: Vec<char> = tag.chars().collect();
+                while i < chars.len() {
+                    if chars[i..].starts_with(&tag_chars) {
+                        current.push_str(&tag);
+                        i += tag_chars.len();
+                        break;
+                    }
+                    current.push(chars[i]);
+                    i += 1;
+                }
+                continue;
+            }
+        }
+
+        if c == ';' {
+            if has_code {
+                statements.push(current.trim().to_string());
+            }
+            current.clear();
+            has_code = false;
+            i += 1;
+            continue;
+        }
+
+        if !c.is_whitespace() {
+            has_code = true;
+        }
+        current.push(c);
+        i += 1;
+    }
+
+    if has_code {
+        statements.push(current.trim().to_string());
+    }
+
+    statements
+}
+
+/// Return the dollar-quote tag (`$$` or `$name$`) starting at `start`, if any
+fn dollar_tag(chars: &[char], start: usize) -> Option<String> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+
+    // `foo$bar` is part of an identifier, not a quote
+    if start > 0 && is_ident(chars[start - 1]) {
+        return None;
+    }
+
+    let mut end = start + 1;
+    while end < chars.len() && is_ident(chars[end]) {
+        end += 1;
+    }
+
+    if end >= chars.len() || chars[end] != '$' {
+        return None;
+    }
+
+    // Positional parameters like `$1` are not tags
+    if chars.get(start + 1).is_some_and(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    Some(chars[start..=end].iter().collect())
+}
+
+/// Entry point for the `migrate` CLI subcommand
+pub async fn execute_command(
+    pool: &DatabasePool,
+    migrations_dir: &str,
+    command: MigrateCommand,
+) -> Result<()> {
+    let migrator = Migrator::from_dir(migrations_dir)?;
+
+    match command {
+        MigrateCommand::Status => {
+            let statuses = migrator.status(pool).await?;
+            println!("{:<40} {:<10} {}", "VERSION", "STATE", "APPLIED AT");
+            for status in statuses {
+                let state = match status.state {
+                    MigrationState::Applied => "applied",
+                    MigrationState::Pending => "pending",
+                    MigrationState::Modified => "MODIFIED",
+                    MigrationState::Missing => "MISSING",
+                };
+                let executed_at = status
+                    .executed_at
+                    .map(|t| t.to_rfc3339())
+                    .unwrap_or_else(|| "-".to_string());
+                println!("{:<40} {:<10} {}", status.version, state, executed_at);
+            }
+        }
+        MigrateCommand::Up { to } => {
+            let count = migrator.up(pool, to.as_deref()).await?;
+            println!("Applied {} migration(s)", count);
+        }
+        MigrateCommand::Down { steps } => {
+            let count = migrator.down(pool, steps).await?;
+            println!("Reverted {} migration(s)", count);
+        }
+        MigrateCommand::Redo { steps } => {
+            let count = migrator.redo(pool, steps).await?;
+            println!("Redid {} migration(s)", count);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_statements_basic() {
+        let statements = split_statements("SELECT 1; SELECT 2;\n-- trailing comment\n");
+        assert_eq!(statements, vec!["SELECT 1", "SELECT 2"]);
+    }
+
+    #[test]
+    fn test_split_statements_keeps_dollar_quoted_bodies() {
+        let sql = r#"
+            DO $$ BEGIN PERFORM 1; PERFORM 2; END $$;
+            CREATE FUNCTION f() RETURNS void AS $body$ BEGIN NULL; END; $body$ LANGUAGE plpgsql;
+        "#;
+        let statements = split_statements(sql);
+        assert_eq!(statements.len(), 2);
+        assert!(statements[0].ends_with("END $$"));
+        assert!(statements[1].contains("$body$ BEGIN NULL; END; $body$"));
+    }
+
+    #[test]
+    fn test_split_statements_ignores_semicolons_in_strings_and_comments() {
+        let sql = "INSERT INTO t VALUES ('a;b', 'it''s'); /* x; y */ -- z;\nSELECT \"a;b\" FROM t";
+        let statements = split_statements(sql);
+        assert_eq!(statements.len(), 2);
+        assert!(statements[0].contains("'a;b', 'it''s'"));
+        assert!(statements[1].ends_with("SELECT \"a;b\" FROM t"));
+    }
+
+    #[test]
+    fn test_positional_parameters_are_not_dollar_quotes() {
+        let statements = split_statements("SELECT $1; SELECT $2");
+        assert_eq!(statements, vec!["SELECT $1", "SELECT $2"]);
+    }
+
+    #[test]
+    fn test_migration_header_directives() {
+        let migration = Migration::new(
+            "007_add_index",
+            "-- description: Add an index\n-- migrate:no-transaction\nCREATE INDEX CONCURRENTLY i ON t(c);"
+                .to_string(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(migration.sequence, 7);
+        assert_eq!(migration.description, "Add an index");
+        assert!(!migration.transactional);
+
+        let migration = Migration::new("008_add_table", "CREATE TABLE t();".to_string(), None).unwrap();
+        assert_eq!(migration.description, "add table");
+        assert!(migration.transactional);
+    }
+
+    #[test]
+    fn test_checksum_ignores_line_endings() {
+        assert_eq!(checksum("SELECT 1;\nSELECT 2;"), checksum("SELECT 1;\r\nSELECT 2;"));
+        assert_ne!(checksum("SELECT 1;"), checksum("SELECT 2;"));
+    }
+
+    #[test]
+    fn test_duplicate_sequence_rejected() {
+        let a = Migration::new("001_a", "SELECT 1;".to_string(), None).unwrap();
+        let b = Migration::new("001_b", "SELECT 1;".to_string(), None).unwrap();
+        assert!(Migrator::from_migrations(vec![a, b]).is_err());
+    }
+}
@@ -0,0 +1,392 @@
+use axum::{
+    async_trait,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+    routing::get,
+    Router,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    config::HealthConfig,
+    database::{self, DatabasePool, RedisPool},
+};
+
+/// State of one dependency, or of the service as a whole. Ordered from best
+/// to worst, so the overall status is the maximum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Healthy,
+    /// Working, but slow or running without something it normally has
+    Degraded,
+    Down,
+}
+
+/// What a [`HealthCheck`] found
+#[derive(Debug, Clone)]
+pub struct CheckOutcome {
+    pub status: HealthStatus,
+    pub message: Option<String>,
+}
+
+impl CheckOutcome {
+    pub fn healthy() -> Self {
+        Self {
+            status: HealthStatus::Healthy,
+            message: None,
+        }
+    }
+
+    pub fn degraded(message: impl Into<String>) -> Self {
+        Self {
+            status: HealthStatus::Degraded,
+            message: Some(message.into()),
+        }
+    }
+
+    pub fn down(message: impl Into<String>) -> Self {
+        Self {
+            status: HealthStatus::Down,
+            message: Some(message.into()),
+        }
+    }
+}
+
+/// A dependency the [`HealthRegistry`] checks
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    /// Key of this check in the report
+    fn name(&self) -> &'static str;
+
+    /// Whether the service is unready while this is down. Non-critical
+    /// dependencies that are down only degrade the service.
+    fn critical(&self) -> bool;
+
+    async fn check(&self) -> CheckOutcome;
+}
+
+/// One dependency in a [`HealthReport`]
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CheckReport {
+    pub status: HealthStatus,
+    pub critical: bool,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// Status of the service and each dependency
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct HealthReport {
+    /// The worst dependency status, except that a non-critical dependency
+    /// that is down only makes the service degraded
+    pub status: HealthStatus,
+    /// False when a critical dependency is down, or while shutting down
+    pub ready: bool,
+    /// Whether the service is shutting down
+    pub draining: bool,
+    pub checked_at: DateTime<Utc>,
+    pub checks: BTreeMap<String, CheckReport>,
+}
+
+impl HealthReport {
+    fn new(checks: BTreeMap<String, CheckReport>, draining: bool) -> Self {
+        let status = checks
+            .values()
+            .map(|check| match check.status {
+                HealthStatus::Down if !check.critical => HealthStatus::Degraded,
+                status => status,
+            })
+            .max()
+            .unwrap_or(HealthStatus::Healthy);
+        let ready = !draining
+            && !checks
+                .values()
+                .any(|check| check.critical && check.status == HealthStatus::Down);
+
+        Self {
+            status,
+            ready,
+            draining,
+            checked_at: Utc::now(),
+            checks,
+        }
+    }
+}
+
+/// Runs every registered [`HealthCheck`] concurrently, each with a timeout
+pub struct HealthRegistry {
+    checks: Vec<Arc<dyn HealthCheck>>,
+    timeout: Duration,
+    draining: CancellationToken,
+}
+
+impl HealthRegistry {
+    /// Readiness fails for good once `draining` is cancelled
+    pub fn new(config: &HealthConfig, draining: CancellationToken) -> Self {
+        Self {
+            checks: Vec::new(),
+            timeout: config.check_timeout,
+            draining,
+        }
+    }
+
+    pub fn register(&mut self, check: impl HealthCheck + 'static) {
+        self.checks.push(Arc::new(check));
+    }
+
+    pub async fn report(&self) -> HealthReport {
+        let checks = self.checks.iter().map(|check| async move {
+            let started = Instant::now();
+            let outcome = tokio::time::timeout(self.timeout, check.check())
+                .await
+                .unwrap_or_else(|_| CheckOutcome::down(format!("timed out after {:?}", self.timeout)));
+
+            if outcome.status != HealthStatus::Healthy {
+                warn!(
+                    "Health check {} is {:?}: {}",
+                    check.name(),
+                    outcome.status,
+                    outcome.message.as_deref().unwrap_or_default()
+                );
+            }
+
+            let report = CheckReport {
+                status: outcome.status,
+                critical: check.critical(),
+                latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+                message: outcome.message,
+            };
+            (check.name().to_string(), report)
+        });
+
+        let checks = futures::future::join_all(checks).await.into_iter().collect();
+        HealthReport::new(checks, self.draining.is_cancelled())
+    }
+}
+
+/// Postgres, without which nothing works. Slow answers or an exhausted pool
+/// count as degraded.
+pub struct DatabaseCheck {
+    pub db: DatabasePool,
+    pub degraded_latency: Duration,
+}
+
+#[async_trait]
+impl HealthCheck for DatabaseCheck {
+    fn name(&self) -> &'static str {
+        "database"
+    }
+
+    fn critical(&self) -> bool {
+        true
+    }
+
+    async fn check(&self) -> CheckOutcome {
+        let started = Instant::now();
+        if !database::health_check(&self.db).await.unwrap_or(false) {
+            return CheckOutcome::down("SELECT 1 failed");
+        }
+
+        let elapsed = started.elapsed();
+        if elapsed > self.degraded_latency {
+            return CheckOutcome::degraded(format!("SELECT 1 took {:?}", elapsed));
+        }
+        if self.db.num_idle() == 0 && self.db.size() >= self.db.options().get_max_connections() {
+            return CheckOutcome::degraded("connection pool exhausted");
+        }
+        CheckOutcome::healthy()
+    }
+}
+
+/// Redis. Rate limiting falls back to in-memory limits without it, so it is
+/// not critical unless `health.redis_critical` is set.
+pub struct RedisCheck {
+    pub redis: RedisPool,
+    pub critical: bool,
+    pub degraded_latency: Duration,
+}
+
+#[async_trait]
+impl HealthCheck for RedisCheck {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    fn critical(&self) -> bool {
+        self.critical
+    }
+
+    async fn check(&self) -> CheckOutcome {
+        let started = Instant::now();
+        if !database::redis_health_check(&self.redis).await.unwrap_or(false) {
+            return CheckOutcome::down("PING failed; rate limiting is using in-memory limits");
+        }
+
+        let elapsed = started.elapsed();
+        if elapsed > self.degraded_latency {
+            return CheckOutcome::degraded(format!("PING took {:?}", elapsed));
+        }
+        CheckOutcome::healthy()
+    }
+}
+
+/// Service and dependency status; always 200 so operators can read it
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "health",
+    responses((status = 200, description = "Health report", body = HealthReport))
+)]
+pub async fn health_check(State(registry): State<Arc<HealthRegistry>>) -> Json<HealthReport> {
+    Json(registry.report().await)
+}
+
+/// 503 while a critical dependency is down or the service is shutting down,
+/// for load balancers and Kubernetes readiness probes
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "Ready to serve traffic", body = HealthReport),
+        (status = 503, description = "A critical dependency is down, or shutting down", body = HealthReport)
+    )
+)]
+pub async fn readiness_check(State(registry): State<Arc<HealthRegistry>>) -> Response {
+    let report = registry.report().await;
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report)).into_response()
+}
+
+/// Whether the process is serving requests at all. Checks no dependencies,
+/// so an outage elsewhere never gets the pod restarted.
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    responses((status = 200, description = "The process is alive"))
+)]
+pub async fn liveness_check() -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "status": "alive" }))
+}
+
+/// `/health`, `/health/ready` and `/health/live`
+pub fn routes<S>(registry: Arc<HealthRegistry>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/health", get(health_check))
+        .route("/health/ready", get(readiness_check))
+        .route("/health/live", get(liveness_check))
+        .with_state(registry)
+}
+
+/// Serve the health routes on their own port, for probes that shouldn't
+/// share the API listener
+pub async fn start_health_server(config: &HealthConfig, registry: Arc<HealthRegistry>) -> anyhow::Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+
+    let bind_addr = format!("{}:{}", config.host, config.port);
+    info!("Starting health check server on {}", bind_addr);
+
+    let listener = tokio::net::TcpListener::bind(&bind_addr).await?;
+    axum::serve(listener, routes::<()>(registry)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed {
+        name: &'static str,
+        critical: bool,
+        outcome: CheckOutcome,
+    }
+
+    #[async_trait]
+    impl HealthCheck for Fixed {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn critical(&self) -> bool {
+            self.critical
+        }
+
+        async fn check(&self) -> CheckOutcome {
+            self.outcome.clone()
+        }
+    }
+
+    fn registry(checks: Vec<(&'static str, bool, CheckOutcome)>) -> HealthRegistry {
+        let mut registry = HealthRegistry {
+            checks: Vec::new(),
+            timeout: Duration::from_secs(1),
+            draining: CancellationToken::new(),
+        };
+        for (name, critical, outcome) in checks {
+            registry.register(Fixed { name, critical, outcome });
+        }
+        registry
+    }
+
+    #[tokio::test]
+    async fn test_only_critical_dependencies_fail_readiness() {
+        let report = registry(vec![
+            ("database", true, CheckOutcome::healthy()),
+            ("redis", false, CheckOutcome::down("PING failed")),
+        ])
+        .report()
+        .await;
+        assert!(report.ready);
+        assert_eq!(report.status, HealthStatus::Degraded);
+        assert_eq!(report.checks["redis"].status, HealthStatus::Down);
+
+        let report = registry(vec![
+            ("database", true, CheckOutcome::down("SELECT 1 failed")),
+            ("redis", false, CheckOutcome::healthy()),
+        ])
+        .report()
+        .await;
+        assert!(!report.ready);
+        assert_eq!(report.status, HealthStatus::Down);
+
+        // Draining fails readiness even with every dependency healthy
+        let registry = registry(vec![("database", true, CheckOutcome::healthy())]);
+        registry.draining.cancel();
+        let report = registry.report().await;
+        assert!(!report.ready && report.draining);
+        assert_eq!(report.status, HealthStatus::Healthy);
+    }
+
+    #[tokio::test]
+    async fn test_report_serializes_for_probes() {
+        let report = registry(vec![("database", true, CheckOutcome::degraded("SELECT 1 took 600ms"))])
+            .report()
+            .await;
+        let json = serde_json::to_value(&report).unwrap();
+
+        assert_eq!(json["status"], "degraded");
+        assert_eq!(json["ready"], true);
+        assert_eq!(json["checks"]["database"]["status"], "degraded");
+        assert_eq!(json["checks"]["database"]["critical"], true);
+        assert!(json["checks"]["database"]["latency_ms"].is_number());
+    }
+}
@@ -0,0 +1,242 @@
+use async_graphql::{InputObject, SimpleObject};
+use axum::{
+    extract::{Query, State},
+    response::Json,
+    routing::get,
+    Router,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+use crate::{
+    cache::{CacheLayer, ResponseCache},
+    config::Config, database::DatabasePool, error::AppError, middleware::scope::RequireScopeLayer, AppState,
+};
+
+/// Range used when a filter gives no start; `performance_summary` itself
+/// only covers the last seven days
+const DEFAULT_RANGE: Duration = Duration::hours(24);
+const DEFAULT_LIMIT: i64 = 1000;
+const MAX_LIMIT: i64 = 10_000;
+
+/// Hourly latency and error figures for one endpoint
+#[derive(Debug, Clone, Serialize, SimpleObject, sqlx::FromRow)]
+pub struct PerformancePoint {
+    pub endpoint: String,
+    pub method: String,
+    pub hour: DateTime<Utc>,
+    pub request_count: i64,
+    pub error_count: i64,
+    pub avg_response_time_ms: f64,
+    pub p95_response_time_ms: f64,
+    pub p99_response_time_ms: f64,
+    /// p99 above `performance.max_response_time_ms`
+    #[sqlx(default)]
+    pub exceeds_slo: bool,
+}
+
+/// One endpoint over the whole range. Percentiles can't be combined across
+/// hours, so the worst hourly p99 stands in for the range.
+#[derive(Debug, Clone, Serialize, SimpleObject, sqlx::FromRow)]
+pub struct EndpointPerformance {
+    pub endpoint: String,
+    pub method: String,
+    pub request_count: i64,
+    pub error_count: i64,
+    pub avg_response_time_ms: f64,
+    pub worst_p99_response_time_ms: f64,
+    /// Hours whose p99 was above `performance.max_response_time_ms`
+    pub slo_breached_hours: i64,
+    pub last_slo_breach: Option<DateTime<Utc>>,
+    #[sqlx(default)]
+    pub exceeds_slo: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, InputObject)]
+pub struct PerformanceFilter {
+    pub endpoint: Option<String>,
+    pub method: Option<String>,
+    /// Start of the range; 24 hours before `to` when unset
+    pub from: Option<DateTime<Utc>>,
+    /// End of the range; now when unset
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
+
+impl PerformanceFilter {
+    fn range(&self) -> Result<(DateTime<Utc>, DateTime<Utc>), AppError> {
+        let to = self.to.unwrap_or_else(Utc::now);
+        let from = self.from.unwrap_or(to - DEFAULT_RANGE);
+        if from >= to {
+            return Err(AppError::BadRequest("from must be before to".to_string()));
+        }
+        Ok((from, to))
+    }
+
+    fn limit(&self) -> Result<i64, AppError> {
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(AppError::BadRequest(format!("limit must be between 1 and {}", MAX_LIMIT)));
+        }
+        Ok(limit)
+    }
+}
+
+/// Reads `performance_summary`, which the `refresh_performance_summary` job
+/// keeps current, and flags endpoints over the latency target
+#[derive(Clone)]
+pub struct PerformanceAnalytics {
+    db: DatabasePool,
+    config: watch::Receiver<Arc<Config>>,
+}
+
+impl PerformanceAnalytics {
+    pub fn new(db: DatabasePool, config: watch::Receiver<Arc<Config>>) -> Self {
+        Self { db, config }
+    }
+
+    /// The p99 latency target in milliseconds
+    pub fn slo_ms(&self) -> f64 {
+        self.config.borrow().performance.max_response_time_ms as f64
+    }
+
+    /// Hourly points, oldest first within each endpoint
+    pub async fn series(&self, filter: &PerformanceFilter) -> Result<Vec<PerformancePoint>, AppError> {
+        let (from, to) = filter.range()?;
+        let slo_ms = self.slo_ms();
+
+        let mut points = sqlx::query_as::<_, PerformancePoint>(
+            "SELECT endpoint, method, hour, request_count, error_count,
+                    avg_response_time::float8 AS avg_response_time_ms,
+                    p95_response_time AS p95_response_time_ms,
+                    p99_response_time AS p99_response_time_ms
+             FROM performance_summary
+             WHERE hour >= date_trunc('hour', $1::timestamptz) AND hour < $2
+               AND ($3::text IS NULL OR endpoint = $3)
+               AND ($4::text IS NULL OR method = upper($4))
+             ORDER BY endpoint, method, hour
+             LIMIT $5",
+        )
+        .bind(from)
+        .bind(to)
+        .bind(filter.endpoint.as_deref())
+        .bind(filter.method.as_deref())
+        .bind(filter.limit()?)
+        .fetch_all(&self.db)
+        .await?;
+
+        for point in &mut points {
+            point.exceeds_slo = point.p99_response_time_ms > slo_ms;
+        }
+        Ok(points)
+    }
+
+    /// Per-endpoint totals, endpoints with the slowest hours first
+    pub async fn endpoints(&self, filter: &PerformanceFilter) -> Result<Vec<EndpointPerformance>, AppError> {
+        let (from, to) = filter.range()?;
+        let slo_ms = self.slo_ms();
+
+        let mut endpoints = sqlx::query_as::<_, EndpointPerformance>(
+            "SELECT endpoint, method,
+                    SUM(request_count)::int8 AS request_count,
+                    SUM(error_count)::int8 AS error_count,
+                    (SUM(avg_response_time * request_count) / SUM(request_count))::float8
+                        AS avg_response_time_ms,
+                    MAX(p99_response_time) AS worst_p99_response_time_ms,
+                    COUNT(*) FILTER (WHERE p99_response_time > $5) AS slo_breached_hours,
+                    MAX(hour) FILTER (WHERE p99_response_time > $5) AS last_slo_breach
+             FROM performance_summary
+             WHERE hour >= date_trunc('hour', $1::timestamptz) AND hour < $2
+               AND ($3::text IS NULL OR endpoint = $3)
+               AND ($4::text IS NULL OR method = upper($4))
+             GROUP BY endpoint, method
+             ORDER BY worst_p99_response_time_ms DESC
+             LIMIT $6",
+        )
+        .bind(from)
+        .bind(to)
+        .bind(filter.endpoint.as_deref())
+        .bind(filter.method.as_deref())
+        .bind(slo_ms)
+        .bind(filter.limit()?)
+        .fetch_all(&self.db)
+        .await?;
+
+        for endpoint in &mut endpoints {
+            endpoint.exceeds_slo = endpoint.slo_breached_hours > 0;
+        }
+        Ok(endpoints)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Report<T> {
+    /// The p99 target points and endpoints are flagged against
+    slo_ms: f64,
+    data: Vec<T>,
+}
+
+/// Tag of cached performance reports
+pub const CACHE_TAG: &str = "performance";
+
+/// `/admin/performance` routes, readable with `metrics:read`. Reports are
+/// cached until `performance_summary` is next refreshed.
+pub fn routes(cache: ResponseCache) -> Router<AppState> {
+    let cached = CacheLayer::new(cache, [CACHE_TAG]);
+
+    Router::new()
+        .route("/", get(endpoint_report).layer(cached.clone()))
+        .route("/series", get(series_report).layer(cached))
+        .route_layer(RequireScopeLayer::new(["metrics:read"]))
+}
+
+async fn endpoint_report(
+    State(state): State<AppState>,
+    Query(filter): Query<PerformanceFilter>,
+) -> Result<Json<Report<EndpointPerformance>>, AppError> {
+    Ok(Json(Report {
+        slo_ms: state.performance.slo_ms(),
+        data: state.performance.endpoints(&filter).await?,
+    }))
+}
+
+async fn series_report(
+    State(state): State<AppState>,
+    Query(filter): Query<PerformanceFilter>,
+) -> Result<Json<Report<PerformancePoint>>, AppError> {
+    Ok(Json(Report {
+        slo_ms: state.performance.slo_ms(),
+        data: state.performance.series(&filter).await?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_defaults_and_bounds() {
+        let to: DateTime<Utc> = "2024-06-01T12:00:00Z".parse().unwrap();
+        let filter = PerformanceFilter {
+            to: Some(to),
+            ..Default::default()
+        };
+        assert_eq!(filter.range().unwrap(), (to - Duration::hours(24), to));
+        assert_eq!(filter.limit().unwrap(), DEFAULT_LIMIT);
+
+        let backwards = PerformanceFilter {
+            from: Some(to),
+            to: Some(to - Duration::hours(1)),
+            ..Default::default()
+        };
+        assert!(backwards.range().is_err());
+
+        let too_many = PerformanceFilter {
+            limit: Some(MAX_LIMIT + 1),
+            ..Default::default()
+        };
+        assert!(too_many.limit().is_err());
+    }
+}
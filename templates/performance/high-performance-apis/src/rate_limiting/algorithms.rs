@@ -0,0 +1,343 @@
+use async_trait::async_trait;
+use redis::Script;
+use std::{sync::Arc, time::Duration};
+
+use crate::config::RateLimitAlgorithmKind;
+
+/// Limit and burst applied by a rate limiting policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateQuota {
+    pub requests_per_second: u32,
+    pub burst_size: u32,
+}
+
+/// Outcome of a single algorithm evaluation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub remaining: u32,
+    /// Time until the quota is fully available again
+    pub reset_after: Duration,
+    /// Time until a rejected request could succeed
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimitDecision {
+    /// Decode the `{allowed, remaining, reset_after_ms, retry_after_ms}` reply
+    /// shared by every script
+    fn from_reply(reply: &[i64]) -> redis::RedisResult<Self> {
+        let [allowed, remaining, reset_after_ms, retry_after_ms] = reply else {
+            return Err(redis::RedisError::from((
+                redis::ErrorKind::TypeError,
+                "unexpected rate limit script reply",
+            )));
+        };
+
+        let allowed = *allowed == 1;
+        Ok(Self {
+            allowed,
+            remaining: (*remaining).clamp(0, u32::MAX as i64) as u32,
+            reset_after: Duration::from_millis((*reset_after_ms).max(0) as u64),
+            retry_after: (!allowed).then(|| Duration::from_millis((*retry_after_ms).max(0) as u64)),
+        })
+    }
+}
+
+/// A rate limiting algorithm evaluated atomically inside Redis.
+///
+/// A `cost` of zero inspects the current state without consuming quota.
+#[async_trait]
+pub trait RateLimitAlgorithm: Send + Sync {
+    /// Short name, also used as a Redis key segment
+    fn name(&self) -> &'static str;
+
+    async fn check(
+        &self,
+        conn: &mut deadpool_redis::Connection,
+        key: &str,
+        quota: RateQuota,
+        cost: u32,
+    ) -> redis::RedisResult<RateLimitDecision>;
+}
+
+/// Build the algorithm implementation for a configured kind
+pub fn algorithm_for(kind: RateLimitAlgorithmKind) -> Arc<dyn RateLimitAlgorithm> {
+    match kind {
+        RateLimitAlgorithmKind::FixedWindow => Arc::new(FixedWindow::new()),
+        RateLimitAlgorithmKind::SlidingLog => Arc::new(SlidingWindowLog::new()),
+        RateLimitAlgorithmKind::TokenBucket => Arc::new(TokenBucket::new()),
+    }
+}
+
+/// Counter per window, sized by [`window_for`]. Cheap, but allows up to
+/// twice the burst across a window boundary.
+pub struct FixedWindow {
+    script: Script,
+}
+
+const FIXED_WINDOW_SCRIPT: &str = r#"
+local limit = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local cost = tonumber(ARGV[3])
+
+local count = tonumber(redis.call('GET', KEYS[1]) or '0')
+local ttl = redis.call('PTTL', KEYS[1])
+if ttl < 0 then ttl = window end
+
+if count + cost > limit then
+    return {0, math.max(limit - count, 0), ttl, ttl}
+end
+
+if cost > 0 then
+    count = redis.call('INCRBY', KEYS[1], cost)
+    if redis.call('PTTL', KEYS[1]) < 0 then
+        redis.call('PEXPIRE', KEYS[1], window)
+    end
+end
+
+return {1, limit - count, ttl, 0}
+"#;
+
+impl FixedWindow {
+    pub fn new() -> Self {
+        Self {
+            script: Script::new(FIXED_WINDOW_SCRIPT),
+        }
+    }
+}
+
+impl Default for FixedWindow {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl RateLimitAlgorithm for FixedWindow {
+    fn name(&self) -> &'static str {
+        "fw"
+    }
+
+    async fn check(
+        &self,
+        conn: &mut deadpool_redis::Connection,
+        key: &str,
+        quota: RateQuota,
+        cost: u32,
+    ) -> redis::RedisResult<RateLimitDecision> {
+        let (window, limit) = window_for(quota);
+        let reply: Vec<i64> = self
+            .script
+            .key(key)
+            .arg(limit)
+            .arg(window.as_millis() as u64)
+            .arg(cost)
+            .invoke_async(conn)
+            .await?;
+
+        RateLimitDecision::from_reply(&reply)
+    }
+}
+
+/// Exact sliding window, sized by [`window_for`], backed by a sorted set of
+/// request timestamps. Precise, at the cost of memory proportional to the
+/// burst per client.
+pub struct SlidingWindowLog {
+    script: Script,
+}
+
+const SLIDING_LOG_SCRIPT: &str = r#"
+redis.replicate_commands()
+local t = redis.call('TIME')
+local now = tonumber(t[1]) * 1000 + math.floor(tonumber(t[2]) / 1000)
+local limit = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local cost = tonumber(ARGV[3])
+
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now - window)
+local count = redis.call('ZCARD', KEYS[1])
+
+local reset_after = window
+local oldest = redis.call('ZRANGE', KEYS[1], 0, 0, 'WITHSCORES')
+if oldest[2] then reset_after = tonumber(oldest[2]) + window - now end
+
+if count + cost > limit then
+    -- The entry that has to fall out of the window before this request fits
+    local index = count + cost - limit - 1
+    local entry = redis.call('ZRANGE', KEYS[1], index, index, 'WITHSCORES')
+    local retry_after = window
+    if entry[2] then retry_after = tonumber(entry[2]) + window - now end
+    return {0, math.max(limit - count, 0), reset_after, retry_after}
+end
+
+for i = 1, cost do
+    redis.call('ZADD', KEYS[1], now, ARGV[4] .. ':' .. i)
+end
+if cost > 0 then
+    redis.call('PEXPIRE', KEYS[1], window)
+end
+
+return {1, limit - count - cost, reset_after, 0}
+"#;
+
+impl SlidingWindowLog {
+    pub fn new() -> Self {
+        Self {
+            script: Script::new(SLIDING_LOG_SCRIPT),
+        }
+    }
+}
+
+impl Default for SlidingWindowLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl RateLimitAlgorithm for SlidingWindowLog {
+    fn name(&self) -> &'static str {
+        "sl"
+    }
+
+    async fn check(
+        &self,
+        conn: &mut deadpool_redis::Connection,
+        key: &str,
+        quota: RateQuota,
+        cost: u32,
+    ) -> redis::RedisResult<RateLimitDecision> {
+        let (window, limit) = window_for(quota);
+        let reply: Vec<i64> = self
+            .script
+            .key(key)
+            .arg(limit)
+            .arg(window.as_millis() as u64)
+            .arg(cost)
+            .arg(uuid::Uuid::new_v4().to_string())
+            .invoke_async(conn)
+            .await?;
+
+        RateLimitDecision::from_reply(&reply)
+    }
+}
+
+/// Token bucket implemented as GCRA: a single "theoretical arrival time" per
+/// client refilled at `requests_per_second`, with `burst_size` capacity.
+pub struct TokenBucket {
+    script: Script,
+}
+
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+redis.replicate_commands()
+local t = redis.call('TIME')
+local now = tonumber(t[1]) * 1000 + tonumber(t[2]) / 1000
+local rate = tonumber(ARGV[1])
+local burst = tonumber(ARGV[2])
+local cost = tonumber(ARGV[3])
+
+local emission = 1000 / rate
+local tolerance = emission * burst
+
+local tat = tonumber(redis.call('GET', KEYS[1]) or now)
+if tat < now then tat = now end
+
+local new_tat = tat + emission * cost
+local allow_at = new_tat - tolerance
+
+if allow_at > now then
+    local remaining = math.floor((tolerance - (tat - now)) / emission)
+    return {0, math.max(remaining, 0), math.ceil(tat - now), math.ceil(allow_at - now)}
+end
+
+if cost > 0 then
+    redis.call('SET', KEYS[1], tostring(new_tat), 'PX', math.max(math.ceil(new_tat - now), 1))
+end
+
+local remaining = math.floor((tolerance - (new_tat - now)) / emission)
+return {1, remaining, math.ceil(new_tat - now), 0}
+"#;
+
+impl TokenBucket {
+    pub fn new() -> Self {
+        Self {
+            script: Script::new(TOKEN_BUCKET_SCRIPT),
+        }
+    }
+}
+
+impl Default for TokenBucket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl RateLimitAlgorithm for TokenBucket {
+    fn name(&self) -> &'static str {
+        "tb"
+    }
+
+    async fn check(
+        &self,
+        conn: &mut deadpool_redis::Connection,
+        key: &str,
+        quota: RateQuota,
+        cost: u32,
+    ) -> redis::RedisResult<RateLimitDecision> {
+        let reply: Vec<i64> = self
+            .script
+            .key(key)
+            .arg(quota.requests_per_second)
+            .arg(quota.burst_size.max(1))
+            .arg(cost)
+            .invoke_async(conn)
+            .await?;
+
+        RateLimitDecision::from_reply(&reply)
+    }
+}
+
+/// Window and per-window limit for the window algorithms. `burst_size`
+/// requests per `burst_size / requests_per_second` admits what the token
+/// bucket and the in-memory fallback do: the burst at once, then
+/// `requests_per_second` sustained.
+pub fn window_for(quota: RateQuota) -> (Duration, u64) {
+    let burst = quota.burst_size.max(1);
+    let window = Duration::from_secs_f64(burst as f64 / quota.requests_per_second.max(1) as f64);
+    (window.max(Duration::from_millis(1)), burst as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decision_from_reply() {
+        let decision = RateLimitDecision::from_reply(&[0, 0, 750, 250]).unwrap();
+        assert!(!decision.allowed);
+        assert_eq!(decision.reset_after, Duration::from_millis(750));
+        assert_eq!(decision.retry_after, Some(Duration::from_millis(250)));
+
+        let decision = RateLimitDecision::from_reply(&[1, 9, 100, 0]).unwrap();
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 9);
+        assert_eq!(decision.retry_after, None);
+
+        assert!(RateLimitDecision::from_reply(&[1, 2]).is_err());
+    }
+
+    #[test]
+    fn test_windows_follow_rate_and_burst() {
+        let quota = |requests_per_second, burst_size| RateQuota {
+            requests_per_second,
+            burst_size,
+        };
+
+        assert_eq!(window_for(quota(100, 100)), (Duration::from_secs(1), 100));
+        assert_eq!(window_for(quota(100, 200)), (Duration::from_secs(2), 200));
+        assert_eq!(window_for(quota(5, 10)), (Duration::from_secs(2), 10));
+        assert_eq!(window_for(quota(50, 10)), (Duration::from_millis(200), 10));
+        assert_eq!(window_for(quota(5000, 1)), (Duration::from_millis(1), 1));
+    }
+}
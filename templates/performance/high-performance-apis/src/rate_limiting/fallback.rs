@@ -0,0 +1,203 @@
+use anyhow::Context;
+use governor::{
+    clock::{Clock, DefaultClock},
+    middleware::StateInformationMiddleware,
+    nanos::Nanos,
+    state::{keyed::DefaultKeyedStateStore, StateStore},
+    Quota, RateLimiter as GovernorRateLimiter,
+};
+use moka::{future::Cache, policy::EvictionPolicy};
+use std::{
+    collections::HashMap,
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::{algorithms::RateQuota, RateLimitInfo, RoutePolicy};
+
+/// Keyed governor state shared by every policy's limiter. Keys are
+/// `policy\0identifier`, so policies never collide, and holding the map
+/// behind an `Arc` lets evictions remove individual keys.
+#[derive(Clone, Default)]
+struct SharedKeyedStore(Arc<DefaultKeyedStateStore<String>>);
+
+impl StateStore for SharedKeyedStore {
+    type Key = String;
+
+    fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        self.0.measure_and_replace(key, f)
+    }
+}
+
+type KeyedLimiter =
+    GovernorRateLimiter<String, SharedKeyedStore, DefaultClock, StateInformationMiddleware>;
+
+/// Requests admitted for one identifier while Redis was unavailable
+#[derive(Debug)]
+pub struct FallbackUsage {
+    pub policy: String,
+    pub identifier: String,
+    consumed: AtomicU32,
+}
+
+impl FallbackUsage {
+    pub fn consumed(&self) -> u32 {
+        self.consumed.load(Ordering::Relaxed)
+    }
+}
+
+/// Per-identity in-memory limiter used while Redis is unreachable.
+///
+/// Memory is bounded by `max_keys`: the least recently used identities, and
+/// any identity idle for longer than `idle_timeout`, are evicted together with
+/// their governor state.
+pub struct FallbackLimiter {
+    store: SharedKeyedStore,
+    // One limiter per policy, rebuilt if the policy's quota changes (plans)
+    limiters: RwLock<HashMap<String, (RateQuota, Arc<KeyedLimiter>)>>,
+    usage: Cache<String, Arc<FallbackUsage>>,
+    clock: DefaultClock,
+}
+
+impl FallbackLimiter {
+    pub fn new<'a>(
+        policies: impl IntoIterator<Item = &'a RoutePolicy>,
+        max_keys: u64,
+        idle_timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        let store = SharedKeyedStore::default();
+        let clock = DefaultClock::default();
+
+        let mut limiters = HashMap::new();
+        for policy in policies {
+            limiters.insert(
+                policy.name.clone(),
+                (policy.quota, Arc::new(build_limiter(policy, &store, &clock)?)),
+            );
+        }
+
+        let evicted_store = store.clone();
+        let usage = Cache::builder()
+            .max_capacity(max_keys)
+            .time_to_idle(idle_timeout)
+            .eviction_policy(EvictionPolicy::lru())
+            .eviction_listener(move |key: Arc<String>, _, _| {
+                evicted_store.0.remove(key.as_str());
+            })
+            .build();
+
+        Ok(Self {
+            store,
+            limiters: RwLock::new(limiters),
+            usage,
+            clock,
+        })
+    }
+
+    fn limiter_for(&self, policy: &RoutePolicy) -> anyhow::Result<Arc<KeyedLimiter>> {
+        if let Some((quota, limiter)) = self.limiters.read().unwrap().get(&policy.name) {
+            if *quota == policy.quota {
+                return Ok(limiter.clone());
+            }
+        }
+
+        let limiter = Arc::new(build_limiter(policy, &self.store, &self.clock)?);
+        self.limiters
+            .write()
+            .unwrap()
+            .insert(policy.name.clone(), (policy.quota, limiter.clone()));
+        Ok(limiter)
+    }
+
+    /// Check and consume one request for `identifier` under `policy`
+    pub async fn check(&self, identifier: &str, policy: &RoutePolicy) -> anyhow::Result<RateLimitInfo> {
+        let limiter = self.limiter_for(policy)?;
+
+        let key = format!("{}\0{}", policy.name, identifier);
+        let usage = self
+            .usage
+            .get_with(key.clone(), async {
+                Arc::new(FallbackUsage {
+                    policy: policy.name.clone(),
+                    identifier: identifier.to_string(),
+                    consumed: AtomicU32::new(0),
+                })
+            })
+            .await;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let result = limiter.check_key(&key);
+
+        let (allowed, remaining, retry_after) = match result {
+            Ok(snapshot) => {
+                usage.consumed.fetch_add(1, Ordering::Relaxed);
+                (true, snapshot.remaining_burst_capacity(), None)
+            }
+            Err(not_until) => {
+                let wait = not_until.wait_time_from(self.clock.now());
+                (false, 0, Some(wait.as_secs_f64().ceil().max(1.0) as u64))
+            }
+        };
+
+        Ok(RateLimitInfo {
+            allowed,
+            limit: policy.quota.requests_per_second,
+            burst: policy.quota.burst_size,
+            policy: policy.name.clone(),
+            requests_remaining: remaining,
+            reset_time: now.as_secs() + retry_after.unwrap_or(1),
+            retry_after,
+        })
+    }
+
+    /// Take every tracked identity and reset the in-memory state, returning
+    /// what was consumed so it can be charged back to Redis
+    pub async fn drain(&self) -> Vec<Arc<FallbackUsage>> {
+        let drained: Vec<Arc<FallbackUsage>> = self
+            .usage
+            .iter()
+            .map(|(_, usage)| usage)
+            .filter(|usage| usage.consumed() > 0)
+            .collect();
+
+        self.usage.invalidate_all();
+        self.usage.run_pending_tasks().await;
+        drained
+    }
+
+    /// Number of identities currently tracked
+    pub async fn tracked_keys(&self) -> u64 {
+        self.usage.run_pending_tasks().await;
+        self.usage.entry_count()
+    }
+
+    /// Number of identities holding governor state
+    pub fn state_keys(&self) -> usize {
+        self.store.0.len()
+    }
+}
+
+fn build_limiter(
+    policy: &RoutePolicy,
+    store: &SharedKeyedStore,
+    clock: &DefaultClock,
+) -> anyhow::Result<KeyedLimiter> {
+    let quota = Quota::per_second(
+        NonZeroU32::new(policy.quota.requests_per_second)
+            .with_context(|| format!("Policy {} needs non-zero requests_per_second", policy.name))?,
+    )
+    .allow_burst(
+        NonZeroU32::new(policy.quota.burst_size)
+            .with_context(|| format!("Policy {} needs non-zero burst_size", policy.name))?,
+    );
+
+    Ok(GovernorRateLimiter::new(quota, store.clone(), clock)
+        .with_middleware::<StateInformationMiddleware>())
+}
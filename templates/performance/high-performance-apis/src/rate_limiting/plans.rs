@@ -0,0 +1,157 @@
+use moka::future::Cache;
+
+use super::{
+    algorithms::{algorithm_for, RateQuota},
+    RoutePolicy,
+};
+use crate::{
+    auth::api_keys::is_lookup_prefix,
+    config::RateLimitingConfig,
+    database::{self, DatabasePool},
+};
+
+/// Channel notified by the triggers in migration 003 whenever plans or API
+/// key plan assignments change
+pub const PLAN_CHANGES_CHANNEL: &str = "rate_limit_plans_changed";
+
+/// A quota plan row from `rate_limit_plans`
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct QuotaPlan {
+    pub name: String,
+    pub requests_per_second: i32,
+    pub burst_size: i32,
+    pub algorithm: String,
+}
+
+impl QuotaPlan {
+    /// Build the rate limiting policy enforced for keys on this plan
+    pub fn to_policy(&self) -> anyhow::Result<RoutePolicy> {
+        Ok(RoutePolicy {
+            name: format!("plan:{}", self.name),
+            quota: RateQuota {
+                requests_per_second: u32::try_from(self.requests_per_second)?,
+                burst_size: u32::try_from(self.burst_size)?,
+            },
+            algorithm: algorithm_for(self.algorithm.parse()?),
+        })
+    }
+}
+
+/// Resolves the quota plan for an API key, caching results (including
+/// "no plan") so the hot path rarely touches Postgres
+#[derive(Clone)]
+pub struct PlanResolver {
+    db: DatabasePool,
+    default_plan: Option<String>,
+    policies: Cache<String, Option<RoutePolicy>>,
+}
+
+impl PlanResolver {
+    pub fn new(db: DatabasePool, config: &RateLimitingConfig) -> Self {
+        let policies = Cache::builder()
+            .max_capacity(config.fallback_max_keys)
+            .time_to_live(config.plan_cache_ttl)
+            .build();
+
+        Self {
+            db,
+            default_plan: config.default_plan.clone(),
+            policies,
+        }
+    }
+
+    /// Policy for the API key with the given lookup prefix, or `None` when
+    /// the key is unknown, expired, revoked or has no plan. Callers pass only
+    /// prefixes of verified keys, so lookups are bounded by the issued keys;
+    /// malformed prefixes are rejected without a query.
+    pub async fn policy_for_key(&self, lookup_prefix: &str) -> anyhow::Result<Option<RoutePolicy>> {
+        if !is_lookup_prefix(lookup_prefix) {
+            return Ok(None);
+        }
+
+        self.policies
+            .try_get_with(lookup_prefix.to_string(), self.load_policy(lookup_prefix))
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to resolve rate limit plan: {}", e))
+    }
+
+    async fn load_policy(&self, lookup_prefix: &str) -> anyhow::Result<Option<RoutePolicy>> {
+        // Explicit plan column, then a `plan:<name>` permission, then the default
+        let plan = sqlx::query_as::<_, QuotaPlan>(
+            r#"
+            SELECT p.name, p.requests_per_second, p.burst_size, p.algorithm
+            FROM api_keys k
+            JOIN rate_limit_plans p ON p.name = COALESCE(
+                k.plan,
+                (SELECT substring(perm FROM 6) FROM unnest(k.permissions) AS perm
+                 WHERE perm LIKE 'plan:%' LIMIT 1),
+                $2
+            )
+            WHERE k.key_prefix = $1
+              AND (k.expires_at IS NULL OR k.expires_at > NOW())
+              AND (k.revoked_at IS NULL OR k.revoked_at > NOW())
+            "#,
+        )
+        .bind(lookup_prefix)
+        .bind(&self.default_plan)
+        .fetch_optional(&self.db)
+        .await?;
+
+        plan.map(|plan| plan.to_policy()).transpose()
+    }
+
+    /// Drop every cached plan so the next request reloads from Postgres
+    pub fn invalidate_all(&self) {
+        self.policies.invalidate_all();
+    }
+
+    /// Invalidate the cache whenever Postgres reports a plan change. Cached
+    /// entries also expire after `plan_cache_ttl`, so a missed notification
+    /// only delays a change rather than losing it.
+    pub async fn listen_for_changes(self) {
+        database::listen_and_invalidate(&self.db, PLAN_CHANGES_CHANNEL, "rate limit plan", || {
+            self.invalidate_all()
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_to_policy() {
+        let plan = QuotaPlan {
+            name: "pro".to_string(),
+            requests_per_second: 200,
+            burst_size: 400,
+            algorithm: "sliding_log".to_string(),
+        };
+
+        let policy = plan.to_policy().unwrap();
+        assert_eq!(policy.name, "plan:pro");
+        assert_eq!(policy.quota.requests_per_second, 200);
+        assert_eq!(policy.quota.burst_size, 400);
+        assert_eq!(policy.algorithm.name(), "sl");
+
+        let invalid = QuotaPlan {
+            algorithm: "leaky".to_string(),
+            ..plan
+        };
+        assert!(invalid.to_policy().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_malformed_prefixes_are_not_looked_up() {
+        // Nothing listens on port 1, so any query would fail
+        let db = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://127.0.0.1:1/plans")
+            .unwrap();
+        let resolver = PlanResolver::new(db, &crate::config::Config::default().rate_limiting);
+
+        for lookup in ["", "0123456789ab.c2VjcmV0", "not-hex-chars", "0123456789abcdef"] {
+            assert!(resolver.policy_for_key(lookup).await.unwrap().is_none(), "{}", lookup);
+        }
+    }
+}
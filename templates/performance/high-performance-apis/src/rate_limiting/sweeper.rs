@@ -0,0 +1,195 @@
+use redis::{AsyncCommands, Script};
+use std::time::{Duration, Instant};
+use tracing::debug;
+use uuid::Uuid;
+
+/// Keys scanned and deleted by one sweep
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SweepStats {
+    pub scanned: u64,
+    pub deleted: u64,
+    pub duration: Duration,
+}
+
+/// Redis lock held by the replica that is currently sweeping.
+///
+/// Acquired with `SET NX PX`; renewal and release only touch the key while it
+/// still holds our token, so an expired lock taken over by another replica is
+/// never extended or deleted by us.
+pub struct SweepLock {
+    key: String,
+    token: String,
+    ttl: Duration,
+}
+
+const RENEW_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+const RELEASE_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+impl SweepLock {
+    /// Try to take the lock, returning `None` if another replica holds it
+    pub async fn acquire(
+        conn: &mut deadpool_redis::Connection,
+        key: String,
+        ttl: Duration,
+    ) -> redis::RedisResult<Option<Self>> {
+        let token = Uuid::new_v4().to_string();
+        let acquired: Option<String> = redis::cmd("SET")
+            .arg(&key)
+            .arg(&token)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl.as_millis() as u64)
+            .query_async(conn)
+            .await?;
+
+        Ok(acquired.map(|_| Self { key, token, ttl }))
+    }
+
+    /// Extend the lock, returning `false` if it was lost
+    pub async fn renew(&self, conn: &mut deadpool_redis::Connection) -> redis::RedisResult<bool> {
+        let renewed: i64 = Script::new(RENEW_SCRIPT)
+            .key(&self.key)
+            .arg(&self.token)
+            .arg(self.ttl.as_millis() as u64)
+            .invoke_async(conn)
+            .await?;
+        Ok(renewed == 1)
+    }
+
+    pub async fn release(self, conn: &mut deadpool_redis::Connection) -> redis::RedisResult<()> {
+        let _: i64 = Script::new(RELEASE_SCRIPT)
+            .key(&self.key)
+            .arg(&self.token)
+            .invoke_async(conn)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Every key written by the rate limiting scripts carries a TTL, so a key
+/// under the prefix without one (`PTTL` of -1) was leaked, e.g. by an older
+/// key layout, and will never expire on its own
+fn keys_without_expiry(keys: Vec<String>, ttls: &[i64]) -> Vec<String> {
+    keys.into_iter()
+        .zip(ttls)
+        .filter(|(_, ttl)| **ttl == -1)
+        .map(|(key, _)| key)
+        .collect()
+}
+
+/// Incrementally walk `{prefix}*` with `SCAN`, unlinking keys without an
+/// expiry. The lock is renewed after every batch and the sweep stops early if
+/// it was lost.
+pub async fn sweep(
+    conn: &mut deadpool_redis::Connection,
+    prefix: &str,
+    batch_size: usize,
+    lock: &SweepLock,
+) -> anyhow::Result<SweepStats> {
+    let started = Instant::now();
+    let pattern = format!("{}*", prefix);
+    let mut stats = SweepStats::default();
+    let mut cursor: u64 = 0;
+
+    loop {
+        let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(&pattern)
+            .arg("COUNT")
+            .arg(batch_size)
+            .query_async(conn)
+            .await?;
+
+        let keys: Vec<String> = keys.into_iter().filter(|key| *key != lock.key).collect();
+        stats.scanned += keys.len() as u64;
+
+        if !keys.is_empty() {
+            let mut pipe = redis::pipe();
+            for key in &keys {
+                pipe.cmd("PTTL").arg(key);
+            }
+            let ttls: Vec<i64> = pipe.query_async(conn).await?;
+
+            let leaked = keys_without_expiry(keys, &ttls);
+            if !leaked.is_empty() {
+                let deleted: u64 = conn.unlink(&leaked).await?;
+                stats.deleted += deleted;
+            }
+        }
+
+        cursor = next;
+        if cursor == 0 {
+            break;
+        }
+
+        if !lock.renew(conn).await? {
+            debug!("Lost rate limit sweeper lock, stopping sweep early");
+            break;
+        }
+    }
+
+    stats.duration = started.elapsed();
+    Ok(stats)
+}
+
+/// Count keys under `prefix` without blocking Redis
+pub async fn count_keys(
+    conn: &mut deadpool_redis::Connection,
+    prefix: &str,
+    batch_size: usize,
+) -> redis::RedisResult<u64> {
+    let pattern = format!("{}*", prefix);
+    let mut count = 0;
+    let mut cursor: u64 = 0;
+
+    loop {
+        let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(&pattern)
+            .arg("COUNT")
+            .arg(batch_size)
+            .query_async(conn)
+            .await?;
+
+        count += keys.len() as u64;
+        cursor = next;
+        if cursor == 0 {
+            return Ok(count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_keys_without_expiry_are_swept() {
+        let keys = vec![
+            "rl:tb:/api:ip:2001:db8::1".to_string(),
+            "rl:fw:default:api_key:abc".to_string(),
+            "rl:legacy:ip:10.0.0.1:1700000000".to_string(),
+            "rl:sl:default:ip:10.0.0.2".to_string(),
+        ];
+        // Live key, live key, leaked key, key expired between SCAN and PTTL
+        let ttls = [950, 12, -1, -2];
+
+        assert_eq!(
+            keys_without_expiry(keys, &ttls),
+            vec!["rl:legacy:ip:10.0.0.1:1700000000".to_string()]
+        );
+    }
+}
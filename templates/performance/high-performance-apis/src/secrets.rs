@@ -0,0 +1,284 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::{collections::BTreeMap, path::PathBuf};
+
+use crate::config::SecretsConfig;
+
+/// Prefix of config values resolved through a [`SecretProvider`], as in
+/// `secret://file/jwt-secret`
+pub const SECRET_REFERENCE_PREFIX: &str = "secret://";
+
+/// A backend that secret references are resolved against
+pub trait SecretProvider: Send + Sync {
+    /// Backend name used in references, e.g. `env` in `secret://env/JWT_SECRET`
+    fn scheme(&self) -> &'static str;
+
+    fn resolve(&self, name: &str) -> anyhow::Result<String>;
+}
+
+/// Secrets held in other environment variables
+pub struct EnvSecretProvider;
+
+impl SecretProvider for EnvSecretProvider {
+    fn scheme(&self) -> &'static str {
+        "env"
+    }
+
+    fn resolve(&self, name: &str) -> anyhow::Result<String> {
+        std::env::var(name).with_context(|| format!("environment variable {} is not set", name))
+    }
+}
+
+/// One secret per file, as mounted by Docker (`/run/secrets`) and Kubernetes
+pub struct FileSecretProvider {
+    dir: PathBuf,
+}
+
+impl FileSecretProvider {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl SecretProvider for FileSecretProvider {
+    fn scheme(&self) -> &'static str {
+        "file"
+    }
+
+    /// `name` is relative to the secrets directory unless absolute
+    fn resolve(&self, name: &str) -> anyhow::Result<String> {
+        let path = self.dir.join(name);
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read secret file {}", path.display()))?;
+        // Mounted secrets commonly end with a newline
+        Ok(contents.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+/// Secrets stored AES-256-GCM encrypted in a JSON file of
+/// `name -> base64(nonce || ciphertext)`, so the file can be committed or
+/// baked into an image while the key is supplied separately
+pub struct EncryptedFileSecretProvider {
+    path: PathBuf,
+    cipher: Aes256Gcm,
+}
+
+impl EncryptedFileSecretProvider {
+    /// `key` is the base64 encoding of a 32-byte key
+    pub fn new(path: impl Into<PathBuf>, key: &str) -> anyhow::Result<Self> {
+        let key = BASE64
+            .decode(key.trim())
+            .context("secrets key is not valid base64")?;
+        let cipher = Aes256Gcm::new_from_slice(&key)
+            .map_err(|_| anyhow::anyhow!("secrets key must be 32 bytes, got {}", key.len()))?;
+
+        Ok(Self {
+            path: path.into(),
+            cipher,
+        })
+    }
+
+    fn read(&self) -> anyhow::Result<BTreeMap<String, String>> {
+        if !self.path.exists() {
+            return Ok(BTreeMap::new());
+        }
+
+        let contents = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("failed to read {}", self.path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("{} is not a JSON object of encrypted secrets", self.path.display()))
+    }
+
+    /// Encrypt `value` and store it under `name`, replacing any previous value
+    pub fn store(&self, name: &str, value: &str) -> anyhow::Result<()> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, value.as_bytes())
+            .map_err(|_| anyhow::anyhow!("failed to encrypt secret {}", name))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+
+        let mut secrets = self.read()?;
+        secrets.insert(name.to_string(), BASE64.encode(sealed));
+        std::fs::write(&self.path, serde_json::to_string_pretty(&secrets)?)
+            .with_context(|| format!("failed to write {}", self.path.display()))
+    }
+}
+
+impl SecretProvider for EncryptedFileSecretProvider {
+    fn scheme(&self) -> &'static str {
+        "encrypted"
+    }
+
+    fn resolve(&self, name: &str) -> anyhow::Result<String> {
+        let secrets = self.read()?;
+        let sealed = secrets
+            .get(name)
+            .with_context(|| format!("no secret named {} in {}", name, self.path.display()))?;
+        let sealed = BASE64
+            .decode(sealed)
+            .with_context(|| format!("secret {} is not valid base64", name))?;
+
+        if sealed.len() < 12 {
+            anyhow::bail!("secret {} is truncated", name);
+        }
+        let (nonce, ciphertext) = sealed.split_at(12);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("failed to decrypt secret {}, wrong key?", name))?;
+
+        Ok(String::from_utf8(plaintext)?)
+    }
+}
+
+/// Resolves `secret://<backend>/<name>` references against the configured
+/// providers; any other value is returned unchanged
+pub struct SecretResolver {
+    providers: Vec<Box<dyn SecretProvider>>,
+}
+
+impl SecretResolver {
+    pub fn new(providers: Vec<Box<dyn SecretProvider>>) -> Self {
+        Self { providers }
+    }
+
+    /// The env and file providers, plus the encrypted file provider when a
+    /// file and key are configured
+    pub fn from_config(config: &SecretsConfig) -> anyhow::Result<Self> {
+        let mut providers: Vec<Box<dyn SecretProvider>> = vec![
+            Box::new(EnvSecretProvider),
+            Box::new(FileSecretProvider::new(&config.dir)),
+        ];
+
+        if let Some(provider) = encrypted_provider(config)? {
+            providers.push(Box::new(provider));
+        }
+
+        Ok(Self::new(providers))
+    }
+
+    pub fn is_reference(value: &str) -> bool {
+        value.starts_with(SECRET_REFERENCE_PREFIX)
+    }
+
+    pub fn resolve(&self, value: &str) -> anyhow::Result<String> {
+        let Some(reference) = value.strip_prefix(SECRET_REFERENCE_PREFIX) else {
+            return Ok(value.to_string());
+        };
+
+        let (scheme, name) = reference
+            .split_once('/')
+            .with_context(|| format!("invalid secret reference {}, expected secret://<backend>/<name>", value))?;
+        let provider = self
+            .providers
+            .iter()
+            .find(|provider| provider.scheme() == scheme)
+            .with_context(|| format!("no secret provider for {}", value))?;
+
+        provider
+            .resolve(name)
+            .with_context(|| format!("failed to resolve {}", value))
+    }
+
+    /// Resolve every reference among the strings of `value`, naming the key
+    /// of any that fails
+    pub fn resolve_all(&self, value: &mut serde_json::Value, path: &str) -> anyhow::Result<()> {
+        match value {
+            serde_json::Value::String(s) if Self::is_reference(s) => {
+                *s = self.resolve(s).with_context(|| path.to_string())?;
+            }
+            serde_json::Value::Object(fields) => {
+                for (name, field) in fields.iter_mut() {
+                    let path = if path.is_empty() {
+                        name.clone()
+                    } else {
+                        format!("{}.{}", path, name)
+                    };
+                    self.resolve_all(field, &path)?;
+                }
+            }
+            serde_json::Value::Array(items) => {
+                for (index, item) in items.iter_mut().enumerate() {
+                    self.resolve_all(item, &format!("{}[{}]", path, index))?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// The encrypted file provider, when both the file and its key are configured
+pub fn encrypted_provider(config: &SecretsConfig) -> anyhow::Result<Option<EncryptedFileSecretProvider>> {
+    let Some(path) = &config.encrypted_file else {
+        return Ok(None);
+    };
+    let Ok(key) = std::env::var(&config.key_env) else {
+        return Ok(None);
+    };
+
+    EncryptedFileSecretProvider::new(path, &key).map(Some)
+}
+
+/// Generate a random base64 key for the encrypted file provider
+pub fn generate_key() -> String {
+    BASE64.encode(Aes256Gcm::generate_key(&mut OsRng))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("secrets-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_file_provider_trims_trailing_newline() {
+        let dir = temp_dir();
+        std::fs::write(dir.join("jwt-secret"), "s3cret-value\n").unwrap();
+
+        let resolver = SecretResolver::new(vec![Box::new(FileSecretProvider::new(&dir))]);
+        assert_eq!(resolver.resolve("secret://file/jwt-secret").unwrap(), "s3cret-value");
+        assert_eq!(resolver.resolve("plain-value").unwrap(), "plain-value");
+        assert!(resolver.resolve("secret://file/missing").is_err());
+        assert!(resolver.resolve("secret://vault/jwt-secret").is_err());
+    }
+
+    #[test]
+    fn test_encrypted_file_round_trip() {
+        let path = temp_dir().join("secrets.enc.json");
+        let key = generate_key();
+
+        let provider = EncryptedFileSecretProvider::new(&path, &key).unwrap();
+        provider.store("database-url", "postgresql://api:hunter2@db/api").unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("hunter2"));
+        assert_eq!(
+            provider.resolve("database-url").unwrap(),
+            "postgresql://api:hunter2@db/api"
+        );
+
+        let wrong_key = EncryptedFileSecretProvider::new(&path, &generate_key()).unwrap();
+        assert!(wrong_key.resolve("database-url").is_err());
+    }
+
+    #[test]
+    fn test_resolve_all_names_the_failing_key() {
+        let resolver = SecretResolver::new(vec![Box::new(FileSecretProvider::new(temp_dir()))]);
+        let mut value = serde_json::json!({
+            "security": { "jwt_secret": "secret://file/absent" }
+        });
+
+        let err = resolver.resolve_all(&mut value, "").unwrap_err();
+        assert!(format!("{:#}", err).starts_with("security.jwt_secret"));
+    }
+}
@@ -0,0 +1,437 @@
+use axum::{
+    extract::ConnectInfo,
+    http::{header, Extensions, HeaderMap, HeaderName, Method, Request, StatusCode, Version},
+    routing::future::RouteFuture,
+    Router,
+};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+};
+use socket2::{SockRef, TcpKeepalive};
+use std::{convert::Infallible, future::Future, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+};
+use tokio_util::sync::CancellationToken;
+use tower::Service;
+use tower_http::{
+    compression::{
+        predicate::{DefaultPredicate, Predicate},
+        CompressionLayer,
+    },
+    cors::{AllowOrigin, CorsLayer},
+    limit::RequestBodyLimitLayer,
+    timeout::TimeoutLayer,
+};
+use tracing::{debug, warn};
+
+use crate::config::Config;
+
+pub mod tls;
+
+use self::tls::{ClientCertificate, TlsAcceptor};
+
+/// Largest request body accepted
+const MAX_REQUEST_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+/// Pause after a failed accept, which usually means the process is out of
+/// file descriptors, so the loop doesn't spin
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Request headers browsers may send cross-origin
+const CORS_ALLOW_HEADERS: [HeaderName; 6] = [
+    header::AUTHORIZATION,
+    header::CONTENT_TYPE,
+    header::ACCEPT,
+    header::CACHE_CONTROL,
+    header::IF_NONE_MATCH,
+    HeaderName::from_static("x-api-key"),
+];
+
+/// Response headers cross-origin scripts may read
+const CORS_EXPOSE_HEADERS: [HeaderName; 7] = [
+    header::RETRY_AFTER,
+    header::ETAG,
+    HeaderName::from_static("x-cache"),
+    HeaderName::from_static("ratelimit-limit"),
+    HeaderName::from_static("ratelimit-remaining"),
+    HeaderName::from_static("ratelimit-reset"),
+    HeaderName::from_static("ratelimit-policy"),
+];
+
+/// Wrap `router` in the outermost HTTP layers: compression
+/// (`performance.enable_compression`), the request deadline
+/// (`server.client_timeout`), the body size limit and CORS
+/// (`security.cors_origins`, re-read on every request so reloads apply)
+pub fn http_layers(router: Router, config: &watch::Receiver<Arc<Config>>) -> Router {
+    let current = config.borrow().clone();
+
+    router
+        .layer(cors_layer(config.clone()))
+        .layer(RequestBodyLimitLayer::new(MAX_REQUEST_BODY_BYTES))
+        .layer(TimeoutLayer::new(current.server.client_timeout))
+        .layer(compression_layer(current.performance.enable_compression))
+}
+
+fn cors_layer(config: watch::Receiver<Arc<Config>>) -> CorsLayer {
+    let allow_origin = AllowOrigin::predicate(move |origin, _| {
+        let config = config.borrow();
+        let origins = &config.security.cors_origins;
+        origins.iter().any(|allowed| allowed == "*" || allowed.as_bytes() == origin.as_bytes())
+    });
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers(CORS_ALLOW_HEADERS)
+        .expose_headers(CORS_EXPOSE_HEADERS)
+}
+
+/// The layer stays in place when compression is off so the stack has one
+/// type; the predicate then turns every response down
+fn compression_layer(enabled: bool) -> CompressionLayer<impl Predicate> {
+    let enabled = move |_: StatusCode, _: Version, _: &HeaderMap, _: &Extensions| enabled;
+    CompressionLayer::new().compress_when(DefaultPredicate::new().and(enabled))
+}
+
+/// hyper connection settings: HTTP/2 only when `performance.enable_http2`,
+/// HTTP/1 keep-alive and HTTP/2 pings from `server.keep_alive`, and the
+/// header read timeout from `server.client_timeout`
+fn connection_builder(config: &Config) -> auto::Builder<TokioExecutor> {
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    let keep_alive = config.server.keep_alive;
+
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .keep_alive(!keep_alive.is_zero())
+        .header_read_timeout(config.server.client_timeout);
+
+    if config.performance.enable_http2 {
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .keep_alive_interval((!keep_alive.is_zero()).then_some(keep_alive));
+        builder
+    } else {
+        builder.http1_only()
+    }
+}
+
+/// Serve `app` until `signal` resolves, then stop accepting and wait for
+/// open connections to finish their in-flight requests. Requests carry
+/// [`ConnectInfo`] with the peer address, like
+/// `into_make_service_with_connect_info`, and over TLS the
+/// [`ClientCertificate`] if the client presented one.
+pub async fn serve<F>(
+    listener: TcpListener,
+    app: Router,
+    config: &Config,
+    tls: Option<TlsAcceptor>,
+    signal: F,
+) -> std::io::Result<()>
+where
+    F: Future<Output = ()>,
+{
+    let builder = Arc::new(connection_builder(config));
+    let keep_alive = config.server.keep_alive;
+    let handshake_timeout = config.server.client_timeout;
+    let closing = CancellationToken::new();
+    // Each connection holds a receiver; `closed` resolves once all are gone
+    let (open_tx, open_rx) = watch::channel(());
+
+    tokio::pin!(signal);
+    loop {
+        let (stream, remote_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Failed to accept connection: {}", e);
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            },
+            _ = &mut signal => break,
+        };
+
+        if let Err(e) = configure_socket(&stream, keep_alive) {
+            debug!("Failed to configure socket for {}: {}", remote_addr, e);
+        }
+
+        let mut service = ConnectionService {
+            app: app.clone(),
+            remote_addr,
+            certificate: None,
+        };
+        let builder = builder.clone();
+        let closing = closing.clone();
+        let tls = tls.clone();
+        let open = open_rx.clone();
+
+        // Handshakes happen here rather than in the accept loop, so a slow
+        // client can't hold up others
+        tokio::spawn(async move {
+            match tls {
+                None => serve_connection(&builder, stream, service, &closing).await,
+                Some(tls) => match tokio::time::timeout(handshake_timeout, tls.accept(stream)).await {
+                    Ok(Ok((stream, certificate))) => {
+                        service.certificate = certificate;
+                        serve_connection(&builder, stream, service, &closing).await
+                    }
+                    Ok(Err(e)) => debug!("TLS handshake with {} failed: {}", remote_addr, e),
+                    Err(_) => debug!("TLS handshake with {} timed out", remote_addr),
+                },
+            }
+            drop(open);
+        });
+    }
+
+    drop(listener);
+    closing.cancel();
+    drop(open_rx);
+    open_tx.closed().await;
+    Ok(())
+}
+
+/// Serve one connection, finishing in-flight requests once `closing` is
+/// cancelled
+async fn serve_connection<I>(
+    builder: &auto::Builder<TokioExecutor>,
+    io: I,
+    service: ConnectionService,
+    closing: &CancellationToken,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let remote_addr = service.remote_addr;
+    let connection = builder.serve_connection_with_upgrades(TokioIo::new(io), service);
+    tokio::pin!(connection);
+
+    let result = tokio::select! {
+        result = connection.as_mut() => result,
+        _ = closing.cancelled() => {
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    };
+    if let Err(e) = result {
+        debug!("Connection from {} closed: {}", remote_addr, e);
+    }
+}
+
+/// The router as seen by one connection, adding what is known about the
+/// peer to each request
+struct ConnectionService {
+    app: Router,
+    remote_addr: SocketAddr,
+    certificate: Option<ClientCertificate>,
+}
+
+impl hyper::service::Service<Request<Incoming>> for ConnectionService {
+    type Response = axum::response::Response;
+    type Error = Infallible;
+    type Future = RouteFuture<Infallible>;
+
+    fn call(&self, mut request: Request<Incoming>) -> Self::Future {
+        request.extensions_mut().insert(ConnectInfo(self.remote_addr));
+        if let Some(certificate) = &self.certificate {
+            request.extensions_mut().insert(certificate.clone());
+        }
+        self.app.clone().call(request)
+    }
+}
+
+/// Disable Nagle and, unless keep-alive is off, probe idle connections
+fn configure_socket(stream: &TcpStream, keep_alive: Duration) -> std::io::Result<()> {
+    stream.set_nodelay(true)?;
+    if !keep_alive.is_zero() {
+        SockRef::from(stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(keep_alive))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tower::ServiceExt;
+
+    fn router() -> Router {
+        Router::new()
+            .route("/", get(|| async { "x".repeat(4096) }))
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                    "done"
+                }),
+            )
+    }
+
+    fn layered(config: Config) -> (Router, watch::Sender<Arc<Config>>) {
+        let (sender, receiver) = watch::channel(Arc::new(config));
+        (http_layers(router(), &receiver), sender)
+    }
+
+    fn get_request(uri: &str, headers: &[(&str, &str)]) -> Request<Body> {
+        let mut request = Request::get(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    /// Serve `router()` on an ephemeral port until the sender is dropped
+    async fn spawn_server(config: Config) -> (SocketAddr, tokio::sync::oneshot::Sender<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(async move {
+            serve(listener, router(), &config, None, async move {
+                let _ = stopped.await;
+            })
+            .await
+            .unwrap();
+        });
+        (addr, stop)
+    }
+
+    /// Send raw HTTP/1.1 and report whether the server closed the
+    /// connection within `wait`
+    async fn closes_within(addr: SocketAddr, request: &[u8], wait: Duration) -> bool {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request).await.unwrap();
+        let mut response = Vec::new();
+        tokio::time::timeout(wait, stream.read_to_end(&mut response)).await.is_ok()
+    }
+
+    #[tokio::test]
+    async fn test_compression_follows_config() {
+        let accept_gzip = [("accept-encoding", "gzip")];
+
+        let (app, _config) = layered(Config::default());
+        let response = app.oneshot(get_request("/", &accept_gzip)).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+
+        let mut config = Config::default();
+        config.performance.enable_compression = false;
+        let (app, _config) = layered(config);
+        let response = app.oneshot(get_request("/", &accept_gzip)).await.unwrap();
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cors_origins_follow_reloads() {
+        let mut config = Config::default();
+        config.security.cors_origins = vec!["https://app.example.com".to_string()];
+        let (app, sender) = layered(config.clone());
+
+        let response = app
+            .clone()
+            .oneshot(get_request("/", &[("origin", "https://app.example.com")]))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+
+        let other = [("origin", "https://evil.example.com")];
+        let response = app.clone().oneshot(get_request("/", &other)).await.unwrap();
+        assert!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+        config.security.cors_origins = vec!["*".to_string()];
+        sender.send(Arc::new(config)).unwrap();
+        let response = app.oneshot(get_request("/", &other)).await.unwrap();
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://evil.example.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_requests_time_out_after_client_timeout() {
+        let mut config = Config::default();
+        config.server.client_timeout = Duration::from_millis(50);
+        let (app, _config) = layered(config);
+
+        let response = tokio::time::timeout(Duration::from_secs(2), app.oneshot(get_request("/slow", &[])))
+            .await
+            .expect("answered before the handler finished")
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_http2_follows_config() {
+        let client = reqwest::Client::builder().http2_prior_knowledge().build().unwrap();
+
+        let (addr, _stop) = spawn_server(Config::default()).await;
+        let response = client.get(format!("http://{}/", addr)).send().await.unwrap();
+        assert_eq!(response.version(), reqwest::Version::HTTP_2);
+
+        let mut config = Config::default();
+        config.performance.enable_http2 = false;
+        let (addr, _stop) = spawn_server(config).await;
+        assert!(client.get(format!("http://{}/", addr)).send().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_and_header_timeout_follow_config() {
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let wait = Duration::from_millis(500);
+
+        // Kept open for the next request
+        let (addr, _stop) = spawn_server(Config::default()).await;
+        assert!(!closes_within(addr, request, wait).await);
+
+        // Closed after the response
+        let mut config = Config::default();
+        config.server.keep_alive = Duration::ZERO;
+        let (addr, _stop) = spawn_server(config).await;
+        assert!(closes_within(addr, request, wait).await);
+
+        // Closed when the headers never finish
+        let mut config = Config::default();
+        config.server.client_timeout = Duration::from_millis(100);
+        let (addr, _stop) = spawn_server(config).await;
+        assert!(closes_within(addr, b"GET / HTTP/1.1\r\n", wait).await);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_in_flight_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let app = Router::new().route(
+            "/",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                "done"
+            }),
+        );
+        let server = tokio::spawn(async move {
+            serve(listener, app, &Config::default(), None, async move {
+                let _ = stopped.await;
+            })
+            .await
+        });
+
+        let request = tokio::spawn(reqwest::get(format!("http://{}/", addr)));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        stop.send(()).unwrap();
+
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.text().await.unwrap(), "done");
+        tokio::time::timeout(Duration::from_secs(1), server)
+            .await
+            .expect("serve returns once connections close")
+            .unwrap()
+            .unwrap();
+    }
+}
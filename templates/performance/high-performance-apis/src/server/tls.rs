@@ -0,0 +1,417 @@
+use anyhow::Context;
+use notify::{RecursiveMode, Watcher};
+use rustls::{
+    crypto::ring,
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    net::TcpStream,
+    sync::{mpsc, watch},
+};
+use tokio_rustls::server::TlsStream;
+use tracing::{error, info, warn};
+
+use crate::config::TlsConfig;
+
+/// A verified client certificate, added to request extensions on mTLS
+/// connections so rate limiting and authentication can identify the caller
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClientCertificate {
+    /// Distinguished name, e.g. `CN=billing, O=Example`
+    pub subject: String,
+    pub common_name: Option<String>,
+    /// Hex SHA-256 of the DER certificate
+    pub fingerprint: String,
+    /// From `server.tls.client_scopes` for the common name
+    pub scopes: Vec<String>,
+}
+
+impl ClientCertificate {
+    fn from_der(der: &CertificateDer<'_>, client_scopes: &BTreeMap<String, Vec<String>>) -> Option<Self> {
+        let (_, certificate) = x509_parser::parse_x509_certificate(der.as_ref()).ok()?;
+        let common_name = certificate
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|name| name.as_str().ok())
+            .map(str::to_string);
+        let scopes = common_name
+            .as_ref()
+            .and_then(|name| client_scopes.get(name))
+            .cloned()
+            .unwrap_or_default();
+
+        Some(Self {
+            subject: certificate.subject().to_string(),
+            common_name,
+            fingerprint: hex::encode(Sha256::digest(der.as_ref())),
+            scopes,
+        })
+    }
+}
+
+/// Completes TLS handshakes with the current certificate
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    config: watch::Receiver<Arc<ServerConfig>>,
+    client_scopes: Arc<BTreeMap<String, Vec<String>>>,
+}
+
+impl TlsAcceptor {
+    /// Handshake, returning the client certificate if one was presented
+    pub async fn accept(
+        &self,
+        stream: TcpStream,
+    ) -> std::io::Result<(TlsStream<TcpStream>, Option<ClientCertificate>)> {
+        let acceptor = tokio_rustls::TlsAcceptor::from(self.config.borrow().clone());
+        let stream = acceptor.accept(stream).await?;
+        let certificate = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|chain| chain.first())
+            .and_then(|leaf| ClientCertificate::from_der(leaf, &self.client_scopes));
+        Ok((stream, certificate))
+    }
+}
+
+/// Rebuilds the TLS configuration when the certificate, key or client CA
+/// files change, so rotated certificates apply to new connections without a
+/// restart. A file that fails to load is reported and the previous
+/// configuration kept.
+pub struct TlsReloader {
+    config: TlsConfig,
+    enable_http2: bool,
+    updates: watch::Sender<Arc<ServerConfig>>,
+}
+
+impl TlsReloader {
+    /// Fails if the configured files can't be loaded
+    pub fn new(config: &TlsConfig, enable_http2: bool) -> anyhow::Result<Self> {
+        let initial = server_config(config, enable_http2)?;
+        let (updates, _) = watch::channel(Arc::new(initial));
+        Ok(Self {
+            config: config.clone(),
+            enable_http2,
+            updates,
+        })
+    }
+
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor {
+            config: self.updates.subscribe(),
+            client_scopes: Arc::new(self.config.client_scopes.clone()),
+        }
+    }
+
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let next = server_config(&self.config, self.enable_http2)?;
+        self.updates.send_replace(Arc::new(next));
+        Ok(())
+    }
+
+    /// Reload whenever a file in a directory holding the certificate, key
+    /// or client CA changes
+    pub async fn run(self) -> anyhow::Result<()> {
+        let (file_events, mut file_changes) = mpsc::channel(1);
+
+        // Watch directories rather than files: Kubernetes updates mounted
+        // secrets by swapping a symlink, and tools like certbot replace files
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if event.is_ok_and(|event| !event.kind.is_access()) {
+                let _ = file_events.try_send(());
+            }
+        })?;
+        for dir in self.watched_dirs() {
+            match watcher.watch(&dir, RecursiveMode::NonRecursive) {
+                Ok(()) => info!("Watching {} for TLS certificate changes", dir.display()),
+                Err(e) => warn!("Not watching {} for TLS certificate changes: {}", dir.display(), e),
+            }
+        }
+
+        while file_changes.recv().await.is_some() {
+            // Let the certificate and key both land before loading them
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            while file_changes.try_recv().is_ok() {}
+
+            match self.reload() {
+                Ok(()) => info!("TLS certificate reloaded"),
+                Err(e) => error!("Keeping the current TLS certificate: {:#}", e),
+            }
+        }
+        Ok(())
+    }
+
+    fn watched_dirs(&self) -> BTreeSet<PathBuf> {
+        [Some(&self.config.cert_path), Some(&self.config.key_path), self.config.client_ca_path.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|path| match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                _ => PathBuf::from("."),
+            })
+            .collect()
+    }
+}
+
+/// rustls settings for `config`, offering h2 over ALPN when HTTP/2 is on
+fn server_config(config: &TlsConfig, enable_http2: bool) -> anyhow::Result<ServerConfig> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions()?;
+
+    let builder = match &config.client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in read_certificates(path)? {
+                roots
+                    .add(certificate)
+                    .with_context(|| format!("invalid CA certificate in {}", path.display()))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if config.client_auth_required {
+                verifier
+            } else {
+                verifier.allow_unauthenticated()
+            };
+            builder.with_client_cert_verifier(verifier.build()?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server = builder
+        .with_single_cert(read_certificates(&config.cert_path)?, read_private_key(&config.key_path)?)
+        .with_context(|| format!("failed to use {} with {}", config.cert_path.display(), config.key_path.display()))?;
+    server.alpn_protocols = if enable_http2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+    Ok(server)
+}
+
+fn read_certificates(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let file = std::fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("failed to read certificates from {}", path.display()))?;
+    if certificates.is_empty() {
+        anyhow::bail!("{} contains no certificates", path.display());
+    }
+    Ok(certificates)
+}
+
+fn read_private_key(path: &Path) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let file = std::fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("failed to read private key from {}", path.display()))?
+        .with_context(|| format!("{} contains no private key", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, server::serve};
+    use axum::{routing::get, Extension, Router};
+    use rcgen::{
+        BasicConstraints, CertificateParams, CertifiedKey, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    };
+    use rustls::{
+        pki_types::{PrivatePkcs8KeyDer, ServerName},
+        ClientConfig,
+    };
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::{client, TlsConnector};
+
+    struct Files {
+        dir: PathBuf,
+    }
+
+    impl Files {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("tls-test-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self { dir }
+        }
+
+        fn write(&self, name: &str, contents: &str) -> PathBuf {
+            let path = self.dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path
+        }
+
+        /// A fresh self-signed `localhost` certificate as `tls.crt` and
+        /// `tls.key`, returning the certificate
+        fn server_certificate(&self) -> CertificateDer<'static> {
+            let CertifiedKey { cert, key_pair } = rcgen::generate_simple_self_signed(["localhost".to_string()]).unwrap();
+            self.write("tls.key", &key_pair.serialize_pem());
+            self.write("tls.crt", &cert.pem());
+            cert.der().clone()
+        }
+
+        fn tls_config(&self) -> TlsConfig {
+            TlsConfig {
+                cert_path: self.dir.join("tls.crt"),
+                key_path: self.dir.join("tls.key"),
+                ..Config::default().server.tls
+            }
+        }
+    }
+
+    impl Drop for Files {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// A client certificate and its PKCS#8 key
+    type Identity = (CertificateDer<'static>, Vec<u8>);
+
+    /// A client CA and a `billing` client certificate it signed
+    fn client_ca(files: &Files) -> (PathBuf, Identity) {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, "Test Client CA");
+        let ca_key = KeyPair::generate().unwrap();
+        let ca = params.self_signed(&ca_key).unwrap();
+
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.distinguished_name.push(DnType::CommonName, "billing");
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+
+        (files.write("client-ca.crt", &ca.pem()), (cert.der().clone(), key.serialize_der()))
+    }
+
+    /// Serve a route that reports the caller's certificate
+    async fn spawn_server(acceptor: TlsAcceptor) -> SocketAddr {
+        let app = Router::new().route(
+            "/",
+            get(|certificate: Option<Extension<ClientCertificate>>| async move {
+                match certificate {
+                    Some(Extension(certificate)) => {
+                        format!("{} {}", certificate.common_name.unwrap_or_default(), certificate.scopes.join(","))
+                    }
+                    None => "anonymous".to_string(),
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            serve(listener, app, &Config::default(), Some(acceptor), std::future::pending()).await
+        });
+        addr
+    }
+
+    async fn connect(
+        addr: SocketAddr,
+        server_certificate: &CertificateDer<'static>,
+        identity: Option<Identity>,
+        alpn: &[&[u8]],
+    ) -> std::io::Result<client::TlsStream<TcpStream>> {
+        let mut roots = RootCertStore::empty();
+        roots.add(server_certificate.clone()).unwrap();
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let mut config = match identity {
+            Some((certificate, key)) => builder
+                .with_client_auth_cert(vec![certificate], PrivatePkcs8KeyDer::from(key).into())
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+
+        let stream = TcpStream::connect(addr).await?;
+        TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+    }
+
+    /// GET / over HTTP/1.1, returning the response body or the I/O error
+    async fn get(mut stream: client::TlsStream<TcpStream>) -> std::io::Result<String> {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok(response.split("\r\n\r\n").nth(1).unwrap_or_default().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_alpn_and_certificate_reload() {
+        let files = Files::new();
+        let first = files.server_certificate();
+        let reloader = TlsReloader::new(&files.tls_config(), true).unwrap();
+        let addr = spawn_server(reloader.acceptor()).await;
+
+        let stream = connect(addr, &first, None, &[b"h2", b"http/1.1"]).await.unwrap();
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+        assert_eq!(get(connect(addr, &first, None, &[b"http/1.1"]).await.unwrap()).await.unwrap(), "anonymous");
+
+        // Without HTTP/2 only http/1.1 is offered
+        let http1 = TlsReloader::new(&files.tls_config(), false).unwrap();
+        let http1_addr = spawn_server(http1.acceptor()).await;
+        let stream = connect(http1_addr, &first, None, &[b"h2", b"http/1.1"]).await.unwrap();
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+
+        // A replaced certificate is served to new connections
+        tokio::spawn(reloader.run());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let second = files.server_certificate();
+        let mut served = None;
+        for _ in 0..50 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            if let Ok(stream) = connect(addr, &second, None, &[b"http/1.1"]).await {
+                served = stream.get_ref().1.peer_certificates().map(|chain| chain[0].clone());
+                break;
+            }
+        }
+        assert_eq!(served, Some(second));
+    }
+
+    #[tokio::test]
+    async fn test_client_certificates_identify_callers() {
+        let files = Files::new();
+        let server_certificate = files.server_certificate();
+        let (ca_path, identity) = client_ca(&files);
+        let mut config = TlsConfig {
+            client_ca_path: Some(ca_path),
+            client_scopes: BTreeMap::from([("billing".to_string(), vec!["metrics:read".to_string()])]),
+            ..files.tls_config()
+        };
+
+        // Optional: callers may present a certificate
+        let addr = spawn_server(TlsReloader::new(&config, true).unwrap().acceptor()).await;
+        let stream = connect(addr, &server_certificate, Some(identity.clone()), &[b"http/1.1"]).await.unwrap();
+        assert_eq!(get(stream).await.unwrap(), "billing metrics:read");
+        let stream = connect(addr, &server_certificate, None, &[b"http/1.1"]).await.unwrap();
+        assert_eq!(get(stream).await.unwrap(), "anonymous");
+
+        // Required: callers without one are refused during the handshake,
+        // which TLS 1.3 clients only see on their first read
+        config.client_auth_required = true;
+        let addr = spawn_server(TlsReloader::new(&config, true).unwrap().acceptor()).await;
+        let refused = match connect(addr, &server_certificate, None, &[b"http/1.1"]).await {
+            Ok(stream) => get(stream).await.map_or(true, |body| body.is_empty()),
+            Err(_) => true,
+        };
+        assert!(refused);
+        let stream = connect(addr, &server_certificate, Some(identity), &[b"http/1.1"]).await.unwrap();
+        assert_eq!(get(stream).await.unwrap(), "billing metrics:read");
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+type Tasks = Arc<Mutex<Vec<(&'static str, JoinHandle<()>)>>>;
+
+/// Coordinates graceful shutdown. On SIGTERM or Ctrl+C readiness starts
+/// failing while requests are still served for `server.client_shutdown`, so
+/// load balancers stop routing here before the listener closes. Once
+/// in-flight requests finish, background tasks are cancelled and queue
+/// writers left to flush.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    /// Cancelled when the signal arrives
+    draining: CancellationToken,
+    /// Cancelled when background tasks should stop
+    stopping: CancellationToken,
+    tasks: Tasks,
+    writers: Tasks,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancelled once a shutdown signal has been received
+    pub fn draining(&self) -> CancellationToken {
+        self.draining.clone()
+    }
+
+    /// Run `task` until it finishes or [`Shutdown::stop_tasks`] cancels it.
+    /// Finished tasks are forgotten, so short-lived ones can be spawned too.
+    pub fn spawn<F>(&self, name: &'static str, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let stopping = self.stopping.clone();
+        let handle = tokio::spawn(async move {
+            tokio::select! {
+                _ = task => {}
+                _ = stopping.cancelled() => {}
+            }
+        });
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|(_, handle)| !handle.is_finished());
+        tasks.push((name, handle));
+    }
+
+    /// Run a queue writer. Writers are never cancelled: they exit once every
+    /// sender is dropped, after writing what is still queued.
+    pub fn spawn_writer<F>(&self, name: &'static str, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.writers.lock().unwrap().push((name, tokio::spawn(task)));
+    }
+
+    /// Resolves once a shutdown signal has arrived and the drain period has
+    /// passed; pass to `with_graceful_shutdown`
+    pub async fn drained(self, drain: Duration) {
+        wait_for_signal().await;
+        self.draining.cancel();
+        info!("Failing readiness, draining for {:?}", drain);
+        tokio::time::sleep(drain).await;
+        info!("Drain period over, finishing in-flight requests");
+    }
+
+    /// Resolves `timeout` after the shutdown signal
+    pub async fn deadline(self, timeout: Duration) {
+        self.draining.cancelled().await;
+        tokio::time::sleep(timeout).await;
+    }
+
+    /// Cancel tasks started with [`Shutdown::spawn`] and wait for them
+    pub async fn stop_tasks(&self) {
+        self.stopping.cancel();
+        join(&self.tasks).await;
+    }
+
+    /// Wait for writers to drain their queues. Returns once every sender
+    /// has been dropped, so drop the application state first.
+    pub async fn wait_for_writers(&self) {
+        join(&self.writers).await;
+    }
+}
+
+async fn join(tasks: &Tasks) {
+    let tasks = std::mem::take(&mut *tasks.lock().unwrap());
+    for (name, handle) in tasks {
+        if let Err(e) = handle.await {
+            warn!("Background task {} failed: {}", name, e);
+        }
+    }
+}
+
+async fn wait_for_signal() {
+    use tokio::signal;
+
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {
+            info!("Received Ctrl+C, shutting down gracefully");
+        },
+        _ = terminate => {
+            info!("Received terminate signal, shutting down gracefully");
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_tasks_are_cancelled_and_writers_drain() {
+        let shutdown = Shutdown::new();
+        shutdown.spawn("forever", std::future::pending());
+
+        let (sender, mut receiver) = mpsc::channel::<u32>(8);
+        let flushed = Arc::new(AtomicBool::new(false));
+        shutdown.spawn_writer("writer", {
+            let flushed = flushed.clone();
+            async move {
+                while receiver.recv().await.is_some() {}
+                flushed.store(true, Ordering::SeqCst);
+            }
+        });
+        sender.send(1).await.unwrap();
+
+        tokio::time::timeout(Duration::from_secs(1), shutdown.stop_tasks())
+            .await
+            .expect("cancelled tasks are joined");
+
+        drop(sender);
+        tokio::time::timeout(Duration::from_secs(1), shutdown.wait_for_writers())
+            .await
+            .expect("writers exit once senders are dropped");
+        assert!(flushed.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_finished_tasks_are_forgotten() {
+        let shutdown = Shutdown::new();
+        for _ in 0..10 {
+            shutdown.spawn("run", async {});
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        shutdown.spawn("forever", std::future::pending());
+
+        assert_eq!(shutdown.tasks.lock().unwrap().len(), 1);
+        tokio::time::timeout(Duration::from_secs(1), shutdown.stop_tasks())
+            .await
+            .expect("cancelled tasks are joined");
+    }
+}
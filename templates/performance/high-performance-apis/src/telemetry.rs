@@ -0,0 +1,138 @@
+use opentelemetry::{
+    trace::{Link, SpanKind, TraceId},
+    Context, KeyValue,
+};
+use opentelemetry_sdk::trace::{Sampler, SamplingResult, ShouldSample};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use tokio::sync::watch;
+use tracing::{info, warn};
+use tracing_subscriber::{
+    layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+
+use crate::config::Config;
+
+/// Parent-based trace ID ratio sampler whose ratio can be changed at runtime
+#[derive(Debug, Clone)]
+pub struct ReloadableSampler {
+    // f64 bits of the current ratio
+    ratio: Arc<AtomicU64>,
+}
+
+impl ReloadableSampler {
+    pub fn new(ratio: f64) -> Self {
+        Self {
+            ratio: Arc::new(AtomicU64::new(ratio.to_bits())),
+        }
+    }
+
+    pub fn ratio(&self) -> f64 {
+        f64::from_bits(self.ratio.load(Ordering::Relaxed))
+    }
+
+    pub fn set_ratio(&self, ratio: f64) {
+        self.ratio.store(ratio.to_bits(), Ordering::Relaxed);
+    }
+}
+
+impl ShouldSample for ReloadableSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        links: &[Link],
+    ) -> SamplingResult {
+        Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(self.ratio()))).should_sample(
+            parent_context,
+            trace_id,
+            name,
+            span_kind,
+            attributes,
+            links,
+        )
+    }
+}
+
+/// Handles for the tracing settings that can change after startup
+pub struct TelemetryHandle {
+    filter: reload::Handle<EnvFilter, Registry>,
+    sampler: ReloadableSampler,
+}
+
+/// Install the global tracing subscriber
+pub fn init_tracing(config: &Config) -> anyhow::Result<TelemetryHandle> {
+    let (filter, filter_handle) = reload::Layer::new(EnvFilter::try_new(&config.tracing.log_filter)?);
+    let sampler = ReloadableSampler::new(config.tracing.sample_rate);
+
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().json());
+
+    // Add OpenTelemetry if configured
+    if config.tracing.enabled {
+        let tracer = opentelemetry_jaeger::new_agent_pipeline()
+            .with_service_name(&config.tracing.service_name)
+            .with_trace_config(opentelemetry_sdk::trace::config().with_sampler(sampler.clone()))
+            .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+
+        let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
+        subscriber.with(telemetry).init();
+    } else {
+        subscriber.init();
+    }
+
+    Ok(TelemetryHandle {
+        filter: filter_handle,
+        sampler,
+    })
+}
+
+impl TelemetryHandle {
+    /// Apply log filter and sample rate changes published by the config
+    /// reloader. Filters are checked by `Config::validate` before publishing.
+    pub async fn watch_config(self, mut updates: watch::Receiver<Arc<Config>>) {
+        let mut log_filter = updates.borrow().tracing.log_filter.clone();
+
+        while updates.changed().await.is_ok() {
+            let tracing_config = updates.borrow_and_update().tracing.clone();
+
+            if tracing_config.log_filter != log_filter {
+                match EnvFilter::try_new(&tracing_config.log_filter)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|filter| Ok(self.filter.reload(filter)?))
+                {
+                    Ok(()) => {
+                        info!("Log filter changed to {}", tracing_config.log_filter);
+                        log_filter = tracing_config.log_filter;
+                    }
+                    Err(e) => warn!("Failed to apply log filter: {}", e),
+                }
+            }
+
+            if tracing_config.sample_rate != self.sampler.ratio() {
+                self.sampler.set_ratio(tracing_config.sample_rate);
+                info!("Trace sample rate changed to {}", tracing_config.sample_rate);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampler_ratio_is_reloadable() {
+        let sampler = ReloadableSampler::new(0.1);
+        let shared = sampler.clone();
+
+        shared.set_ratio(0.75);
+        assert_eq!(sampler.ratio(), 0.75);
+    }
+}